@@ -0,0 +1,99 @@
+use std::time::{Duration, Instant};
+
+use byteorder::{ByteOrder, LittleEndian};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use tokio::io::{duplex, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::runtime::Runtime;
+use unicom_lib::arch::unix::{read_message, write_message, UnixMessage, UnixReader, UnixWriter};
+
+const SIZES: [usize; 4] = [128, 4 * 1024, 64 * 1024, 1024 * 1024];
+const PIPE_SIZE: usize = 64 * 1024;
+
+/// Previous framing: body copied through a 1024 byte buffer, one write per step.
+/// The head is read with `read_exact` so the comparison does not fail on short reads.
+mod chunked_loop{
+    use super::*;
+
+    pub async fn write<W: AsyncWrite + Unpin>(writer: &mut W, id: u64, body: &[u8]){
+        let mut buf: [u8; 13] = [0; 13];
+        buf[0] = 2;
+        LittleEndian::write_u64(&mut buf[1..9], id);
+        LittleEndian::write_u32(&mut buf[9..13], body.len() as u32);
+        writer.write_all(&buf).await.unwrap();
+        for chunk in body.chunks(1024){
+            writer.write_all(chunk).await.unwrap();
+        }
+    }
+
+    pub async fn read<R: AsyncRead + Unpin>(reader: &mut R) -> Vec<u8>{
+        let mut buf: [u8; 13] = [0; 13];
+        reader.read_exact(&mut buf).await.unwrap();
+        let size = LittleEndian::read_u32(&buf[9..13]) as usize;
+        let mut ret = Vec::new();
+        let mut csize = 0;
+        while csize < size{
+            let rsize = (size - csize).min(1024);
+            let mut buf: [u8; 1024] = [0; 1024];
+            let n = reader.read(&mut buf[..rsize]).await.unwrap();
+            csize += n;
+            ret.extend_from_slice(&buf[..n]);
+        }
+        ret
+    }
+}
+
+fn chunked_loop(runtime: &Runtime, size: usize, iters: u64) -> Duration{
+    runtime.block_on(async move {
+        let (client, mut server) = duplex(PIPE_SIZE);
+        let body = vec![7u8; size];
+        let start = Instant::now();
+        let writer = tokio::spawn(async move {
+            let mut client = client;
+            for id in 0..iters{
+                chunked_loop::write(&mut client, id, &body).await;
+            }
+        });
+        for _ in 0..iters{
+            chunked_loop::read(&mut server).await;
+        }
+        writer.await.unwrap();
+        start.elapsed()
+    })
+}
+
+fn codec(runtime: &Runtime, size: usize, iters: u64) -> Duration{
+    runtime.block_on(async move {
+        let (client, server) = duplex(PIPE_SIZE);
+        let mut reader = UnixReader::new(server);
+        let start = Instant::now();
+        let writer = tokio::spawn(async move {
+            let mut writer = UnixWriter::new(client);
+            for id in 0..iters{
+                write_message(&mut writer, UnixMessage::Response { id, data: vec![7u8; size] }).await.unwrap();
+            }
+        });
+        for _ in 0..iters{
+            read_message(&mut reader).await.unwrap();
+        }
+        writer.await.unwrap();
+        start.elapsed()
+    })
+}
+
+fn framing(c: &mut Criterion){
+    let runtime = Runtime::new().unwrap();
+    let mut group = c.benchmark_group("response_frame");
+    for size in SIZES{
+        group.throughput(Throughput::Bytes(size as u64));
+        group.bench_with_input(BenchmarkId::new("chunked_loop", size), &size, |b, &size| {
+            b.iter_custom(|iters| chunked_loop(&runtime, size, iters))
+        });
+        group.bench_with_input(BenchmarkId::new("codec", size), &size, |b, &size| {
+            b.iter_custom(|iters| codec(&runtime, size, iters))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, framing);
+criterion_main!(benches);
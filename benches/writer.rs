@@ -0,0 +1,168 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use tokio::io::{duplex, DuplexStream};
+use tokio::runtime::Runtime;
+use tokio::sync::Mutex;
+use unicom_lib::arch::unix::{read_message, write_message, UnixMessage, UnixReader, UnixWriter};
+use unicom_lib::arch::writer::WriterTask;
+
+const PIPE_SIZE: usize = 64 * 1024;
+const BULK_SIZE: usize = 256 * 1024;
+const SENDERS: u64 = 16;
+/// Pause of each small sender between two frames, as a handler doing some work
+const PACE: Duration = Duration::from_millis(1);
+
+/// Where the small frames go: behind a mutex like before, or through a writer task
+#[derive(Clone, Copy)]
+enum Path{
+    Mutex,
+    Task{ priority: bool },
+}
+
+#[derive(Clone)]
+enum Sink{
+    Mutex(Arc<Mutex<UnixWriter<DuplexStream>>>),
+    Task(unicom_lib::arch::writer::FrameSender),
+}
+
+impl Sink{
+    async fn send(&self, message: UnixMessage) -> bool{
+        match self{
+            Sink::Mutex(writer) => write_message(&mut *writer.lock().await, message).await.is_ok(),
+            Sink::Task(sender) => sender.send(message, Vec::new()).await.is_ok(),
+        }
+    }
+}
+
+/// Mean latency of small pong frames sent by paced concurrent tasks while large responses keep the
+/// socket busy. Each pong carries its send time so the reader measures how long it waited
+fn latency(runtime: &Runtime, path: Path, iters: u64) -> Duration{
+    runtime.block_on(async move {
+        let (client, server) = duplex(PIPE_SIZE);
+        let writer = UnixWriter::new(client);
+        let (sink, task) = match path{
+            Path::Mutex => (Sink::Mutex(Arc::new(Mutex::new(writer))), None),
+            Path::Task { priority } => {
+                let task = WriterTask::spawn(writer, None, 64, priority);
+                (Sink::Task(task.sender()), Some(task))
+            },
+        };
+        let start = Instant::now();
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let bulk = {
+            let sink = sink.clone();
+            let stop = stop.clone();
+            tokio::spawn(async move {
+                let mut id = 0;
+                while !stop.load(Ordering::Relaxed){
+                    id += 1;
+                    if !sink.send(UnixMessage::Response { id, data: vec![7u8; BULK_SIZE] }).await{
+                        break
+                    }
+                }
+            })
+        };
+        let mut senders = Vec::new();
+        for sender in 0..SENDERS{
+            let sink = sink.clone();
+            let count = iters / SENDERS + u64::from(sender < iters % SENDERS);
+            senders.push(tokio::spawn(async move {
+                for _ in 0..count{
+                    sink.send(UnixMessage::Pong { id: start.elapsed().as_nanos() as u64 }).await;
+                    tokio::time::sleep(PACE).await;
+                }
+            }));
+        }
+        drop(sink);
+
+        let mut reader = UnixReader::new(server);
+        let mut total = Duration::ZERO;
+        let mut received = 0;
+        while received < iters{
+            if let UnixMessage::Pong { id } = read_message(&mut reader).await.unwrap(){
+                total += start.elapsed() - Duration::from_nanos(id);
+                received += 1;
+            }
+        }
+        stop.store(true, Ordering::Relaxed);
+        // keep reading so the bulk sender can finish, the pipe closes with the last writer
+        let drain = tokio::spawn(async move {
+            while read_message(&mut reader).await.is_ok(){}
+        });
+        for sender in senders{
+            sender.await.unwrap();
+        }
+        bulk.await.unwrap();
+        if let Some(task) = task{
+            task.close(Duration::from_secs(60)).await;
+        }
+        drain.await.unwrap();
+        total
+    })
+}
+
+/// Time to deliver small responses sent by concurrent tasks with nothing else on the socket
+fn throughput(runtime: &Runtime, path: Path, iters: u64) -> Duration{
+    runtime.block_on(async move {
+        let (client, server) = duplex(PIPE_SIZE);
+        let writer = UnixWriter::new(client);
+        let (sink, task) = match path{
+            Path::Mutex => (Sink::Mutex(Arc::new(Mutex::new(writer))), None),
+            Path::Task { priority } => {
+                let task = WriterTask::spawn(writer, None, 64, priority);
+                (Sink::Task(task.sender()), Some(task))
+            },
+        };
+        let start = Instant::now();
+        let mut senders = Vec::new();
+        for sender in 0..SENDERS{
+            let sink = sink.clone();
+            let count = iters / SENDERS + u64::from(sender < iters % SENDERS);
+            senders.push(tokio::spawn(async move {
+                for id in 0..count{
+                    sink.send(UnixMessage::Response { id, data: vec![7u8; 128] }).await;
+                }
+            }));
+        }
+        drop(sink);
+        let mut reader = UnixReader::new(server);
+        for _ in 0..iters{
+            read_message(&mut reader).await.unwrap();
+        }
+        let elapsed = start.elapsed();
+        for sender in senders{
+            sender.await.unwrap();
+        }
+        if let Some(task) = task{
+            task.close(Duration::from_secs(60)).await;
+        }
+        elapsed
+    })
+}
+
+fn writer(c: &mut Criterion){
+    let runtime = Runtime::new().unwrap();
+    let mut group = c.benchmark_group("small_frame_latency");
+    for (name, path) in [("mutex", Path::Mutex), ("task", Path::Task { priority: false }), ("task_priority", Path::Task { priority: true })]{
+        group.bench_with_input(BenchmarkId::new(name, BULK_SIZE), &path, |b, &path| {
+            b.iter_custom(|iters| latency(&runtime, path, iters))
+        });
+    }
+    group.finish();
+
+    let mut group = c.benchmark_group("small_frame_throughput");
+    group.throughput(Throughput::Elements(1));
+    for (name, path) in [("mutex", Path::Mutex), ("task", Path::Task { priority: false })]{
+        group.bench_with_input(BenchmarkId::new(name, 128), &path, |b, &path| {
+            b.iter_custom(|iters| throughput(&runtime, path, iters))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, writer);
+criterion_main!(benches);
@@ -0,0 +1,56 @@
+use std::{collections::HashMap, sync::{Mutex, OnceLock}};
+
+use tokio::io::{duplex, split, DuplexStream, ReadHalf, WriteHalf};
+use tokio::sync::mpsc;
+
+use crate::error::{UnicomError, UnicomErrorKind};
+
+/// Bytes buffered in each direction of an in-memory connection
+pub const PIPE_SIZE: usize = 64 * 1024;
+
+type Listeners = Mutex<HashMap<String, mpsc::UnboundedSender<DuplexStream>>>;
+
+fn listeners() -> &'static Listeners{
+    static LISTENERS: OnceLock<Listeners> = OnceLock::new();
+    LISTENERS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// In-process stand-in for a hub socket, reachable at `memory://<name>` until dropped
+pub struct MemoryListener{
+    name: String,
+    incoming: mpsc::UnboundedReceiver<DuplexStream>,
+}
+
+impl MemoryListener{
+    pub fn bind(name: &str) -> Result<MemoryListener, UnicomError>{
+        let mut listeners = listeners().lock().unwrap();
+        if listeners.contains_key(name){
+            return Err(UnicomError::new(UnicomErrorKind::NotAllowed, &format!("memory address {} already in use", name)))
+        }
+        let (sender, incoming) = mpsc::unbounded_channel();
+        listeners.insert(name.to_string(), sender);
+        Ok(MemoryListener { name: name.to_string(), incoming })
+    }
+
+    pub async fn accept(&mut self) -> Result<(ReadHalf<DuplexStream>, WriteHalf<DuplexStream>), UnicomError>{
+        match self.incoming.recv().await{
+            Some(stream) => Ok(split(stream)),
+            None => Err(UnicomError::new(UnicomErrorKind::LostConnection, "memory listener closed")),
+        }
+    }
+}
+
+impl Drop for MemoryListener{
+    fn drop(&mut self) {
+        listeners().lock().unwrap().remove(&self.name);
+    }
+}
+
+pub fn connect(name: &str) -> Result<(ReadHalf<DuplexStream>, WriteHalf<DuplexStream>), UnicomError>{
+    let listeners = listeners().lock().unwrap();
+    let listener = listeners.get(name)
+        .ok_or_else(|| UnicomError::new(UnicomErrorKind::NotAllowed, &format!("memory address {} not bound", name)))?;
+    let (client, server) = duplex(PIPE_SIZE);
+    listener.send(server).map_err(|_| UnicomError::new(UnicomErrorKind::NotAllowed, &format!("memory address {} not bound", name)))?;
+    Ok(split(client))
+}
@@ -1 +1,99 @@
-pub mod unix;
\ No newline at end of file
+use std::{fmt, path::PathBuf, str::FromStr};
+
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use crate::error::{UnicomError, UnicomErrorKind};
+use self::unix::{FdQueue, PeerCredentials};
+
+pub mod unix;
+pub mod tcp;
+pub mod memory;
+pub mod writer;
+
+pub type BoxReader = Box<dyn AsyncRead + Send + Unpin>;
+pub type BoxWriter = Box<dyn AsyncWrite + Send + Unpin>;
+
+/// Where the hub listens: `unix:///run/unicom.sock` or `tcp://127.0.0.1:7000`, a bare path is a unix socket.
+/// `memory://<name>` reaches a `memory::MemoryListener` in the same process.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Address{
+    Unix(PathBuf),
+    Tcp(String),
+    Memory(String),
+}
+
+impl FromStr for Address{
+    type Err = UnicomError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(path) = s.strip_prefix("unix://"){
+            return Ok(Address::Unix(PathBuf::from(path)))
+        }
+        if let Some(addr) = s.strip_prefix("tcp://"){
+            return Ok(Address::Tcp(addr.to_string()))
+        }
+        if let Some(name) = s.strip_prefix("memory://"){
+            return Ok(Address::Memory(name.to_string()))
+        }
+        if s.contains("://"){
+            return Err(UnicomError::new(UnicomErrorKind::ParameterInvalid, &format!("address scheme unknown {}", s)))
+        }
+        Ok(Address::Unix(PathBuf::from(s)))
+    }
+}
+
+impl fmt::Display for Address{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self{
+            Address::Unix(path) => write!(f, "unix://{}", path.display()),
+            Address::Tcp(addr) => write!(f, "tcp://{}", addr),
+            Address::Memory(name) => write!(f, "memory://{}", name),
+        }
+    }
+}
+
+/// Descriptor queues of a transport able to pass file descriptors
+#[derive(Clone)]
+pub struct FdChannel{
+    pub received: FdQueue,
+    pub outgoing: FdQueue,
+}
+
+/// Both halves of a hub connection and what the transport offers beside the byte stream
+pub struct Connection{
+    pub reader: BoxReader,
+    pub writer: BoxWriter,
+    pub files: Option<FdChannel>,
+    /// Process on the other end, known for unix sockets only
+    pub credentials: Option<PeerCredentials>,
+}
+
+impl Connection{
+    pub fn new<R, W>(reader: R, writer: W) -> Connection
+    where R: AsyncRead + Send + Unpin + 'static, W: AsyncWrite + Send + Unpin + 'static{
+        Connection { reader: Box::new(reader), writer: Box::new(writer), files: None, credentials: None }
+    }
+}
+
+/// Open a connection to the hub, every transport shares the framing of `arch::unix`
+pub async fn connect(address: &Address) -> Result<Connection, UnicomError>{
+    match address{
+        Address::Unix(path) => {
+            let (reader, writer) = unix::connect(path).await?;
+            let files = FdChannel { received: reader.received(), outgoing: writer.outgoing() };
+            let credentials = reader.peer_credentials()?;
+            let mut connection = Connection::new(reader, writer);
+            connection.files = Some(files);
+            connection.credentials = Some(credentials);
+            Ok(connection)
+        },
+        Address::Tcp(addr) => {
+            let (reader, writer) = tcp::connect(addr).await?;
+            Ok(Connection::new(reader, writer))
+        },
+        Address::Memory(name) => {
+            let (reader, writer) = memory::connect(name)?;
+            Ok(Connection::new(reader, writer))
+        },
+    }
+}
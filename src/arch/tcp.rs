@@ -0,0 +1,17 @@
+use tokio::net::{TcpListener, TcpStream, ToSocketAddrs};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+
+use crate::error::UnicomError;
+
+pub async fn connect<A: ToSocketAddrs>(addr: A) -> Result<(OwnedReadHalf, OwnedWriteHalf), UnicomError>{
+    let stream = TcpStream::connect(addr).await?;
+    // frames are written whole, waiting for more data only adds latency
+    stream.set_nodelay(true)?;
+    Ok(stream.into_split())
+}
+
+pub async fn accept(listener: &TcpListener) -> Result<(OwnedReadHalf, OwnedWriteHalf), UnicomError>{
+    let (stream, _) = listener.accept().await?;
+    stream.set_nodelay(true)?;
+    Ok(stream.into_split())
+}
@@ -1,12 +1,29 @@
+use std::collections::VecDeque;
+use std::io::{self, IoSlice, IoSliceMut};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{ready, Context, Poll};
+
+use bytes::{Buf, BufMut, BytesMut};
+use nix::cmsg_space;
+use nix::libc::{cmsghdr, CMSG_LEN, SCM_RIGHTS, SOL_SOCKET};
+use nix::sys::socket::{recvmsg, sendmsg, ControlMessage, ControlMessageOwned, MsgFlags};
+use serde::Serialize;
+use tokio::io::{ErrorKind, Interest, ReadBuf};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{UnixListener, UnixStream};
 use tokio::net::unix::{OwnedReadHalf, OwnedWriteHalf};
-use tokio::io::ErrorKind;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use byteorder::{ByteOrder, LittleEndian};
+use crate::config::{ConnectionConfig, PeerPolicy};
 use crate::error::{UnicomError, UnicomErrorKind};
-use crate::node::NodeConfig;
-use crate::node::message::request::UnicomRequest;
+use crate::node::{api::ApiUpdate, NodeConfig};
+use crate::node::message::{request::UnicomRequest, response::ResponseHead};
+use crate::UserLevel;
 
 #[derive(Debug)]
+#[allow(clippy::large_enum_variant)]
 pub enum UnixMessage{
     Error{
         id: u64,
@@ -20,149 +37,756 @@ pub enum UnixMessage{
         id: u64,
         data: UnicomRequest
     },
-    Quit
-}
-
-async fn read_head(reader: &mut OwnedReadHalf) -> Result<(u8, u64, usize), UnicomError>{
-    let mut buf: [u8; 13] = [0; 13];
-    match reader.read(&mut buf).await {
-        Ok(13) => Ok((buf[0], LittleEndian::read_u64(&buf[1..9]),LittleEndian::read_u32(&buf[9..13]) as usize)),
-        Ok(0) => Err(UnicomError::new(UnicomErrorKind::LostConnection, "lost connection")),
-        Ok(_) => Err(UnicomError::new(UnicomErrorKind::DataInvalid, "message head length error")),
-        Err(e) => Err(e.into()),
-    }
-}
-
-async fn read_body(reader: &mut OwnedReadHalf, size: usize) -> Result<Vec<u8>, UnicomError>{
-    let mut ret = Vec::new();
-    let mut csize = 0;
-    loop{
-        if size == 0{
-            break;
-        }
-        let mut rsize = size-csize;
-        if rsize > 1024{
-            rsize = 1024
-        }
-        
-        let mut buf: [u8; 1024] = [0; 1024];
-        match reader.read(&mut buf[..rsize]).await {
-            Ok(0) => return Err(UnicomError::new(UnicomErrorKind::LostConnection, "lost connection")),
-            Ok(n) => {
-                csize += n;
-                ret.extend_from_slice(&buf[..n]);
-                if csize >= size{
-                    break;
-                }
+    /// Part of a streamed request or response body, an empty `data` ends the stream
+    Chunk{
+        id: u64,
+        origin: ChunkOrigin,
+        data: Vec<u8>,
+    },
+    Quit,
+    /// The request with this id is not awaited anymore, its handler should stop
+    Cancel{
+        id: u64,
+    },
+    /// Liveness probe, answered with a pong carrying the same id
+    Ping{
+        id: u64,
+    },
+    Pong{
+        id: u64,
+    },
+    /// Announces `count` file descriptors sent as SCM_RIGHTS with this frame, they belong to
+    /// the request or response with the same id that follows
+    Files{
+        id: u64,
+        origin: ChunkOrigin,
+        count: usize,
+    },
+    /// Api added or removed by a running node, the hub updates its api list
+    Apis{
+        update: ApiUpdate,
+    },
+    /// Status, headers and content type of the response with the same id that follows
+    Head{
+        id: u64,
+        head: ResponseHead,
+    },
+    /// Frame refused by the reader because it is larger than the maximum frame size, or an error or request
+    /// that can not be parsed, its body was skipped. Writing it sends the error back to the peer as an error frame.
+    Rejected{
+        id: u64,
+        origin: ChunkOrigin,
+        error: UnicomError,
+    },
+    /// The receiver of the body with this id takes `credit` more chunk frames, `origin` tells which body
+    Window{
+        id: u64,
+        origin: ChunkOrigin,
+        credit: u32,
+    },
+    /// Any other frame that can not be decoded, its body was skipped and the next frame is read as usual.
+    /// Writing it sends the error back to the peer as an error frame.
+    Invalid{
+        id: u64,
+        error: UnicomError,
+    },
+}
 
-            },
-            Err(e) => {
-                if e.kind() != ErrorKind::WouldBlock{
-                    return Err(e.into());
+/// Tells which body a chunk belongs to, request and response ids are not shared
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ChunkOrigin{
+    Request,
+    Response,
+}
+
+impl From<ChunkOrigin> for u8{
+    fn from(origin: ChunkOrigin) -> Self {
+        match origin{
+            ChunkOrigin::Request => 1,
+            ChunkOrigin::Response => 2,
+        }
+    }
+}
+
+impl TryFrom<u8> for ChunkOrigin{
+    type Error = UnicomError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value{
+            1 => Ok(ChunkOrigin::Request),
+            2 => Ok(ChunkOrigin::Response),
+            _ => Err(UnicomError::new(UnicomErrorKind::DataInvalid, "chunk origin unknown")),
+        }
+    }
+}
+
+/// Maximum payload carried by one chunk frame
+pub const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Chunk frames of a body sent before the receiver grants more with window frames
+pub const INITIAL_WINDOW: usize = 16;
+
+/// Version of the wire protocol spoken by this library
+pub const PROTOCOL_VERSION: u16 = 1;
+/// Oldest wire protocol version this library can still talk
+pub const MIN_PROTOCOL_VERSION: u16 = 1;
+
+/// Optional protocol features, negotiated during the init handshake
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Capabilities(u32);
+
+impl Capabilities{
+    /// Bodies sent as chunk frames (kind 3)
+    pub const STREAMING: Capabilities = Capabilities(1);
+    /// Requests aborted with cancel frames (kind 5)
+    pub const CANCELLATION: Capabilities = Capabilities(2);
+    /// Ping and pong frames (kinds 6 and 7)
+    pub const HEARTBEAT: Capabilities = Capabilities(4);
+    /// File descriptors passed with files frames (kind 8), only on unix sockets
+    pub const FILE_DESCRIPTORS: Capabilities = Capabilities(8);
+    /// Api list updates from a running node (kind 9)
+    pub const DYNAMIC_APIS: Capabilities = Capabilities(16);
+    /// Status, headers and content type of responses in head frames (kind 10)
+    pub const RESPONSE_HEAD: Capabilities = Capabilities(32);
+    /// Chunk frames sent against the credit granted by window frames (kind 11)
+    pub const FLOW_CONTROL: Capabilities = Capabilities(64);
+
+    pub const fn empty() -> Capabilities{
+        Capabilities(0)
+    }
+
+    /// Every capability implemented by this library
+    pub const fn supported() -> Capabilities{
+        Capabilities(Capabilities::STREAMING.0 | Capabilities::CANCELLATION.0 | Capabilities::HEARTBEAT.0 | Capabilities::FILE_DESCRIPTORS.0 | Capabilities::DYNAMIC_APIS.0 | Capabilities::RESPONSE_HEAD.0 | Capabilities::FLOW_CONTROL.0)
+    }
+
+    pub const fn bits(&self) -> u32{
+        self.0
+    }
+
+    pub const fn from_bits(bits: u32) -> Capabilities{
+        Capabilities(bits & Capabilities::supported().0)
+    }
+
+    pub const fn contains(&self, other: Capabilities) -> bool{
+        self.0 & other.0 == other.0
+    }
+
+    pub const fn intersection(&self, other: Capabilities) -> Capabilities{
+        Capabilities(self.0 & other.0)
+    }
+
+    pub fn insert(&mut self, other: Capabilities){
+        self.0 |= other.0
+    }
+
+    pub fn remove(&mut self, other: Capabilities){
+        self.0 &= !other.0
+    }
+}
+
+/// Result of the init handshake, what both peers agreed to use on the connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Handshake{
+    pub version: u16,
+    pub capabilities: Capabilities,
+}
+
+impl Default for Handshake{
+    fn default() -> Self {
+        Handshake { version: PROTOCOL_VERSION, capabilities: Capabilities::supported() }
+    }
+}
+
+impl Handshake{
+    pub fn new(capabilities: Capabilities) -> Handshake{
+        Handshake { version: PROTOCOL_VERSION, capabilities: capabilities.intersection(Capabilities::supported()) }
+    }
+
+    /// Agree on the highest common version and the shared capabilities
+    pub fn negotiate(&self, remote: &Handshake) -> Result<Handshake, UnicomError>{
+        let version = self.version.min(remote.version);
+        if version < MIN_PROTOCOL_VERSION{
+            return Err(UnicomError::new(UnicomErrorKind::VersionMismatch, 
+                &format!("protocol version {} not supported, expected {} to {}", remote.version, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION)))
+        }
+        Ok(Handshake { version, capabilities: self.capabilities.intersection(remote.capabilities) })
+    }
+
+    /// Refuse messages relying on a capability that was not negotiated
+    pub fn check(&self, message: &UnixMessage) -> Result<(), UnicomError>{
+        let needed = match message{
+            UnixMessage::Chunk { .. } => Capabilities::STREAMING,
+            UnixMessage::Cancel { .. } => Capabilities::CANCELLATION,
+            UnixMessage::Ping { .. } | UnixMessage::Pong { .. } => Capabilities::HEARTBEAT,
+            UnixMessage::Files { .. } => Capabilities::FILE_DESCRIPTORS,
+            UnixMessage::Apis { .. } => Capabilities::DYNAMIC_APIS,
+            UnixMessage::Head { .. } => Capabilities::RESPONSE_HEAD,
+            UnixMessage::Window { .. } => Capabilities::FLOW_CONTROL,
+            _ => Capabilities::empty(),
+        };
+        if !self.capabilities.contains(needed){
+            return Err(UnicomError::new(UnicomErrorKind::NotAllowed, &format!("capability {:?} not negotiated", needed)))
+        }
+        Ok(())
+    }
+
+    fn to_id(self) -> u64{
+        ((self.version as u64) << 32) | self.capabilities.bits() as u64
+    }
+
+    fn from_id(id: u64) -> Handshake{
+        Handshake { version: (id >> 32) as u16, capabilities: Capabilities::from_bits(id as u32) }
+    }
+}
+
+/// Identity of the process on the other end of a unix socket, read with SO_PEERCRED
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PeerCredentials{
+    pub uid: u32,
+    pub gid: u32,
+    pub pid: Option<i32>,
+}
+
+impl PeerCredentials{
+    pub fn from_stream(stream: &UnixStream) -> Result<PeerCredentials, UnicomError>{
+        let credentials = stream.peer_cred()?;
+        Ok(PeerCredentials { uid: credentials.uid(), gid: credentials.gid(), pid: credentials.pid() })
+    }
+}
+
+pub async fn connect<P: AsRef<Path>>(path: P) -> Result<(FdReader, FdWriter), UnicomError>{
+    let (reader, writer) = UnixStream::connect(path).await?.into_split();
+    Ok((FdReader::new(reader), FdWriter::new(writer)))
+}
+
+pub async fn accept(listener: &UnixListener) -> Result<(FdReader, FdWriter), UnicomError>{
+    let (stream, _) = listener.accept().await?;
+    let (reader, writer) = stream.into_split();
+    Ok((FdReader::new(reader), FdWriter::new(writer)))
+}
+
+/// Most descriptors carried by one files frame
+pub const MAX_FILES: usize = 32;
+
+/// Most descriptors waiting for their files frame, more are closed as they arrive
+const MAX_RECEIVED_FILES: usize = 4 * MAX_FILES;
+
+/// File descriptors travelling next to the byte stream, in the order they were sent or received
+pub type FdQueue = Arc<Mutex<VecDeque<OwnedFd>>>;
+
+/// Reading half of a unix socket that keeps the descriptors received as SCM_RIGHTS
+pub struct FdReader{
+    inner: OwnedReadHalf,
+    received: FdQueue,
+}
+
+impl FdReader{
+    pub fn new(inner: OwnedReadHalf) -> FdReader{
+        FdReader { inner, received: FdQueue::default() }
+    }
+
+    pub fn received(&self) -> FdQueue{
+        self.received.clone()
+    }
+
+    pub fn get_ref(&self) -> &UnixStream{
+        self.inner.as_ref()
+    }
+
+    pub fn peer_credentials(&self) -> Result<PeerCredentials, UnicomError>{
+        PeerCredentials::from_stream(self.get_ref())
+    }
+}
+
+impl AsyncRead for FdReader{
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let stream: &UnixStream = this.inner.as_ref();
+        loop{
+            ready!(stream.poll_read_ready(cx))?;
+            let unfilled = buf.initialize_unfilled();
+            let ret = stream.try_io(Interest::READABLE, || {
+                let mut iov = [IoSliceMut::new(unfilled)];
+                let mut cmsg = cmsg_space!([RawFd; MAX_FILES]);
+                // zeroed so the control buffer can be read back when nix refuses to parse it
+                cmsg.resize(cmsg.capacity(), 0);
+                cmsg.clear();
+                let msg = recvmsg::<()>(stream.as_raw_fd(), &mut iov, Some(&mut cmsg), MsgFlags::MSG_CMSG_CLOEXEC)?;
+                let (size, mut fds) = (msg.bytes, Vec::new());
+                let truncated = match msg.cmsgs(){
+                    Ok(messages) => {
+                        for message in messages{
+                            if let ControlMessageOwned::ScmRights(received) = message{
+                                fds.extend(received);
+                            }
+                        }
+                        false
+                    },
+                    Err(_) => true,
+                };
+                if truncated{
+                    // recvmsg fills the capacity of the buffer without changing its length
+                    fds = truncated_rights(unsafe { std::slice::from_raw_parts(cmsg.as_ptr(), cmsg.capacity()) });
                 }
+                Ok((size, fds, truncated))
+            });
+            match ret{
+                Ok((size, fds, truncated)) => {
+                    // the kernel handed the descriptors over, they are ours to close
+                    let fds: Vec<OwnedFd> = fds.into_iter().map(|fd| unsafe { OwnedFd::from_raw_fd(fd) }).collect();
+                    let mut received = this.received.lock().unwrap();
+                    if truncated{
+                        println!("error receive files: more than {} descriptors in one write, {} closed", MAX_FILES, fds.len());
+                    }
+                    else if received.len() + fds.len() > MAX_RECEIVED_FILES{
+                        println!("error receive files: more than {} descriptors waiting, {} closed", MAX_RECEIVED_FILES, fds.len());
+                    }
+                    else{
+                        received.extend(fds);
+                    }
+                    buf.advance(size);
+                    return Poll::Ready(Ok(()))
+                },
+                Err(e) if e.kind() == ErrorKind::WouldBlock => continue,
+                Err(e) => return Poll::Ready(Err(e)),
             }
-        };
-        
-    } 
-    Ok(ret)
+        }
+    }
 }
 
-pub async fn read_init(reader: &mut OwnedReadHalf) -> Result<NodeConfig, UnicomError>{
-    let (code, _id, size) = read_head(reader).await?;
-    if code != 0x42{
-        return Err(UnicomError::new(UnicomErrorKind::ParseError, "Code security not 0x42"))
+/// Descriptors of a SCM_RIGHTS message cut by the kernel. nix refuses to parse a truncated control buffer,
+/// yet the descriptors that fit were installed in this process and must be closed
+fn truncated_rights(cmsg: &[u8]) -> Vec<RawFd>{
+    if cmsg.len() < size_of::<cmsghdr>(){
+        return Vec::new()
+    }
+    let header = unsafe { std::ptr::read_unaligned(cmsg.as_ptr() as *const cmsghdr) };
+    if header.cmsg_level != SOL_SOCKET || header.cmsg_type != SCM_RIGHTS{
+        return Vec::new()
     }
-    Ok(NodeConfig::from_utf8(read_body(reader, size).await?)?)
+    let start = unsafe { CMSG_LEN(0) } as usize;
+    let end = (header.cmsg_len as usize).min(cmsg.len());
+    cmsg.get(start..end).unwrap_or_default()
+        .chunks_exact(size_of::<RawFd>())
+        .map(|fd| RawFd::from_ne_bytes(fd.try_into().unwrap()))
+        .collect()
 }
 
-pub async fn read_message(reader: &mut OwnedReadHalf) -> Result<UnixMessage, UnicomError>{
-    let (kind, id, size) = read_head(reader).await?;
-    match kind {
-        0 => Ok(UnixMessage::Error{
-            id,
-            error: UnicomError::from_utf8(read_body(reader, size).await?)?,
-        }),
-        1 => Ok(UnixMessage::Request{
-            id,
-            data: UnicomRequest::from_utf8(read_body(reader, size).await?)?,
-        }),
-        2 => Ok(UnixMessage::Response{
-            id,
-            data: read_body(reader, size).await?
-        }),
-        3 => todo!(),
-        4 => Ok(UnixMessage::Quit),
-        _ => Err(UnicomError::new(UnicomErrorKind::DataInvalid, "kind message unknown"))
+/// Writing half of a unix socket, queued descriptors are attached to the next write as SCM_RIGHTS
+pub struct FdWriter{
+    inner: OwnedWriteHalf,
+    outgoing: FdQueue,
+}
+
+impl FdWriter{
+    pub fn new(inner: OwnedWriteHalf) -> FdWriter{
+        FdWriter { inner, outgoing: FdQueue::default() }
+    }
+
+    pub fn outgoing(&self) -> FdQueue{
+        self.outgoing.clone()
+    }
+
+    pub fn get_ref(&self) -> &UnixStream{
+        self.inner.as_ref()
+    }
+}
+
+impl AsyncWrite for FdWriter{
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let fds: Vec<RawFd> = this.outgoing.lock().unwrap().iter().map(|fd| fd.as_raw_fd()).collect();
+        if fds.is_empty() || buf.is_empty(){
+            return Pin::new(&mut this.inner).poll_write(cx, buf)
+        }
+        let stream: &UnixStream = this.inner.as_ref();
+        loop{
+            ready!(stream.poll_write_ready(cx))?;
+            let ret = stream.try_io(Interest::WRITABLE, || {
+                Ok(sendmsg::<()>(stream.as_raw_fd(), &[IoSlice::new(buf)], &[ControlMessage::ScmRights(&fds)], MsgFlags::MSG_NOSIGNAL, None)?)
+            });
+            match ret{
+                Ok(size) => {
+                    // the peer holds its own copies now
+                    this.outgoing.lock().unwrap().clear();
+                    return Poll::Ready(Ok(size))
+                },
+                Err(e) if e.kind() == ErrorKind::WouldBlock => continue,
+                Err(e) => return Poll::Ready(Err(e)),
+            }
+        }
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
     }
 }
 
-async fn write_head(writer: &mut OwnedWriteHalf, kind: u8, id: u64, size: usize)-> Result<(), UnicomError>{
-    let mut buf: [u8; 13] = [0; 13];
-    buf[0] = kind;
-    LittleEndian::write_u64(&mut buf[1..9], id);
-    LittleEndian::write_u32(&mut buf[9..13], size as u32);
-    writer.write(&buf).await?;
+/// Size of the frame head: kind (u8), id (u64) and body size (u32)
+pub const HEAD_SIZE: usize = 13;
+
+/// Bodies up to this size are copied next to the head and sent in a single write
+const INLINE_BODY: usize = 16 * 1024;
+
+fn encode_head(head: &mut [u8], kind: u8, id: u64, size: usize) -> Result<(), UnicomError>{
+    let size: u32 = size.try_into().map_err(|_| UnicomError::new(UnicomErrorKind::DataInvalid, "message body too large for one frame"))?;
+    head[0] = kind;
+    LittleEndian::write_u64(&mut head[1..9], id);
+    LittleEndian::write_u32(&mut head[9..13], size);
     Ok(())
 }
 
-async fn write_body(writer: &mut OwnedWriteHalf, body: &[u8])-> Result<(), UnicomError>{
-    let mut current = 0;
-    loop{
-        if current < body.len(){
-            let mut next = current + 1024;
-            if next > body.len(){
-                next = body.len();
+fn lost_connection(e: std::io::Error) -> UnicomError{
+    if e.kind() == ErrorKind::UnexpectedEof{
+        return UnicomError::new(UnicomErrorKind::LostConnection, "lost connection")
+    }
+    e.into()
+}
+
+/// Frame decoder over any byte stream, bytes read past the current frame are kept for the next one
+pub struct UnixReader<R>{
+    reader: R,
+    buffer: BytesMut,
+    max_frame_size: usize,
+}
+
+impl<R: AsyncRead + Unpin> UnixReader<R>{
+    pub fn new(reader: R) -> UnixReader<R>{
+        UnixReader::with_max_frame_size(reader, ConnectionConfig::default().max_frame_size)
+    }
+
+    /// Frames with a body larger than `max_frame_size` are skipped without being allocated
+    pub fn with_max_frame_size(reader: R, max_frame_size: usize) -> UnixReader<R>{
+        UnixReader { reader, buffer: BytesMut::with_capacity(INLINE_BODY), max_frame_size }
+    }
+
+    pub fn max_frame_size(&self) -> usize{
+        self.max_frame_size
+    }
+
+    /// Bytes read from the stream but not decoded yet
+    pub fn buffered(&self) -> usize{
+        self.buffer.len()
+    }
+
+    pub fn get_ref(&self) -> &R{
+        &self.reader
+    }
+
+    pub fn into_inner(self) -> R{
+        self.reader
+    }
+
+    async fn read_head(&mut self) -> Result<(u8, u64, usize), UnicomError>{
+        while self.buffer.len() < HEAD_SIZE{
+            if self.reader.read_buf(&mut self.buffer).await? == 0{
+                if self.buffer.is_empty(){
+                    return Err(UnicomError::new(UnicomErrorKind::LostConnection, "lost connection"))
+                }
+                return Err(UnicomError::new(UnicomErrorKind::DataInvalid, "message head length error"))
+            }
+        }
+        let head = self.buffer.split_to(HEAD_SIZE);
+        Ok((head[0], LittleEndian::read_u64(&head[1..9]), LittleEndian::read_u32(&head[9..13]) as usize))
+    }
+
+    fn check_size(&self, size: usize) -> Result<(), UnicomError>{
+        if size > self.max_frame_size{
+            return Err(UnicomError::new(UnicomErrorKind::OutOfMemory, 
+                &format!("frame of {} bytes exceeds the maximum of {} bytes", size, self.max_frame_size)))
+        }
+        Ok(())
+    }
+
+    /// First byte of the body without consuming it
+    async fn peek(&mut self) -> Result<u8, UnicomError>{
+        while self.buffer.is_empty(){
+            if self.reader.read_buf(&mut self.buffer).await? == 0{
+                return Err(UnicomError::new(UnicomErrorKind::LostConnection, "lost connection"))
             }
-            writer.write(&body[current..next]).await?;
-            current = next;
-            
+        }
+        Ok(self.buffer[0])
+    }
+
+    async fn skip_body(&mut self, size: usize) -> Result<(), UnicomError>{
+        let buffered = self.buffer.len().min(size);
+        self.buffer.advance(buffered);
+        let rest = (size - buffered) as u64;
+        let skipped = tokio::io::copy(&mut (&mut self.reader).take(rest), &mut tokio::io::sink()).await?;
+        if skipped < rest{
+            return Err(UnicomError::new(UnicomErrorKind::LostConnection, "lost connection"))
+        }
+        Ok(())
+    }
+
+    async fn read_body(&mut self, size: usize) -> Result<Vec<u8>, UnicomError>{
+        let mut body = vec![0; size];
+        let buffered = self.buffer.len().min(size);
+        body[..buffered].copy_from_slice(&self.buffer[..buffered]);
+        self.buffer.advance(buffered);
+        // the rest of a large body goes straight into its own allocation
+        self.reader.read_exact(&mut body[buffered..]).await.map_err(lost_connection)?;
+        Ok(body)
+    }
+}
+
+/// Frame encoder over any byte stream, the encoding buffer is reused between frames
+pub struct UnixWriter<W>{
+    writer: W,
+    buffer: BytesMut,
+}
+
+impl<W: AsyncWrite + Unpin> UnixWriter<W>{
+    pub fn new(writer: W) -> UnixWriter<W>{
+        UnixWriter { writer, buffer: BytesMut::with_capacity(INLINE_BODY + HEAD_SIZE) }
+    }
+
+    pub fn get_ref(&self) -> &W{
+        &self.writer
+    }
+
+    pub fn into_inner(self) -> W{
+        self.writer
+    }
+
+    /// Bytes of queued frames not written yet
+    pub fn buffered(&self) -> usize{
+        self.buffer.len()
+    }
+
+    /// Write the queued frames
+    pub async fn flush(&mut self) -> Result<(), UnicomError>{
+        if !self.buffer.is_empty(){
+            self.writer.write_all(&self.buffer).await?;
+            self.buffer.clear();
+        }
+        self.writer.flush().await?;
+        Ok(())
+    }
+
+    /// Append a frame after the queued ones, a large body goes to the socket right away with everything before it
+    pub async fn queue_message(&mut self, message: UnixMessage) -> Result<(), UnicomError>{
+        match message {
+            UnixMessage::Response { id, data} => self.queue_frame(2, id, &[], &data).await,
+            UnixMessage::Request { id, data } => self.queue_json(1, id, &data),
+            UnixMessage::Chunk { id, origin, data } => self.queue_frame(3, id, &[origin.into()], &data).await,
+            UnixMessage::Quit => self.queue_frame(4, 0, &[], &[]).await,
+            UnixMessage::Cancel { id } => self.queue_frame(5, id, &[], &[]).await,
+            UnixMessage::Ping { id } => self.queue_frame(6, id, &[], &[]).await,
+            UnixMessage::Pong { id } => self.queue_frame(7, id, &[], &[]).await,
+            UnixMessage::Files { id, origin, count } => {
+                let mut body = [origin.into(), 0, 0, 0, 0];
+                LittleEndian::write_u32(&mut body[1..5], count as u32);
+                self.queue_frame(8, id, &[], &body).await
+            },
+            UnixMessage::Apis { update } => self.queue_json(9, 0, &update),
+            UnixMessage::Head { id, head } => self.queue_json(10, id, &head),
+            UnixMessage::Window { id, origin, credit } => {
+                let mut body = [origin.into(), 0, 0, 0, 0];
+                LittleEndian::write_u32(&mut body[1..5], credit);
+                self.queue_frame(11, id, &[], &body).await
+            },
+            UnixMessage::Error { id, error } => self.queue_json(0, id, &error),
+            UnixMessage::Rejected { id, error, .. } | UnixMessage::Invalid { id, error } => self.queue_json(0, id, &error),
+        }
+    }
+
+    async fn queue_frame(&mut self, kind: u8, id: u64, prefix: &[u8], body: &[u8]) -> Result<(), UnicomError>{
+        let start = self.buffer.len();
+        self.buffer.put_bytes(0, HEAD_SIZE);
+        if let Err(e) = encode_head(&mut self.buffer[start..], kind, id, prefix.len() + body.len()){
+            self.buffer.truncate(start);
+            return Err(e)
+        }
+        self.buffer.extend_from_slice(prefix);
+        if body.len() <= INLINE_BODY{
+            self.buffer.extend_from_slice(body);
         }
         else{
-            break;
+            self.writer.write_all(&self.buffer).await?;
+            self.buffer.clear();
+            self.writer.write_all(body).await?;
         }
+        Ok(())
+    }
+
+    fn queue_json<T: Serialize>(&mut self, kind: u8, id: u64, value: &T) -> Result<(), UnicomError>{
+        let start = self.buffer.len();
+        self.buffer.put_bytes(0, HEAD_SIZE);
+        if let Err(e) = serde_json::to_writer((&mut self.buffer).writer(), value){
+            self.buffer.truncate(start);
+            return Err(e.into())
+        }
+        let size = self.buffer.len() - start - HEAD_SIZE;
+        if let Err(e) = encode_head(&mut self.buffer[start..], kind, id, size){
+            self.buffer.truncate(start);
+            return Err(e)
+        }
+        Ok(())
+    }
+
+    async fn write_frame(&mut self, kind: u8, id: u64, prefix: &[u8], body: &[u8]) -> Result<(), UnicomError>{
+        self.queue_frame(kind, id, prefix, body).await?;
+        self.flush().await
+    }
+
+    async fn write_json<T: Serialize>(&mut self, kind: u8, id: u64, value: &T) -> Result<(), UnicomError>{
+        self.queue_json(kind, id, value)?;
+        self.flush().await
     }
-    Ok(())
 }
 
-pub async fn write_init(writer: &mut OwnedWriteHalf, config: &NodeConfig) -> Result<(), UnicomError>{
-    let json_data = match serde_json::to_string(&config){
-        Ok(data) => data.as_bytes().to_vec(),
-        Err(_) => return Err(UnicomError::new(UnicomErrorKind::ParseError, "json init invalid")),
+/// Read the node init frame and negotiate it against what the hub offers
+pub async fn read_init<R: AsyncRead + Unpin>(reader: &mut UnixReader<R>, offer: &Handshake) -> Result<(NodeConfig, Handshake), UnicomError>{
+    let (code, id, size) = reader.read_head().await?;
+    if code != 0x42{
+        return Err(UnicomError::new(UnicomErrorKind::ParseError, "Code security not 0x42"))
+    }
+    reader.check_size(size)?;
+    let body = reader.read_body(size).await?;
+    let handshake = offer.negotiate(&Handshake::from_id(id))?;
+    Ok((NodeConfig::from_utf8(body)?, handshake))
+}
+
+/// Hub side of the init exchange: the node name is checked against `policy` for the peer `credentials` before
+/// answering the handshake. A refused node, or one whose version or config is not accepted, gets an error
+/// frame at id 0 and the error is returned
+pub async fn read_registration<R, W>(reader: &mut UnixReader<R>, writer: &mut UnixWriter<W>, offer: &Handshake, credentials: Option<&PeerCredentials>, policy: &PeerPolicy) -> Result<(NodeConfig, Handshake, UserLevel), UnicomError>
+where R: AsyncRead + Unpin, W: AsyncWrite + Unpin{
+    let (config, handshake) = match read_init(reader, offer).await{
+        Ok(init) => init,
+        Err(error) => {
+            // a node with another version or a broken config is told why, a lost connection can not be
+            if !matches!(error.kind(), UnicomErrorKind::LostConnection){
+                if let Err(e) = write_message(writer, UnixMessage::Error { id: 0, error: error.clone() }).await{
+                    println!("error write init refusal {:?}", e);
+                }
+            }
+            return Err(error)
+        },
     };
-    write_head(writer, 0x42, 0, json_data.len()).await?;
-    write_body(writer, &json_data).await?;
-    Ok(())
+    match policy.authorize(credentials, &config.name){
+        Ok(level) => {
+            write_handshake(writer, &handshake).await?;
+            Ok((config, handshake, level))
+        },
+        Err(error) => {
+            write_message(writer, UnixMessage::Error { id: 0, error: error.clone() }).await?;
+            Err(error)
+        },
+    }
+}
+
+/// Read the hub answer to the init frame, a config or version error comes back as an error frame at id 0
+pub async fn read_handshake<R: AsyncRead + Unpin>(reader: &mut UnixReader<R>, offer: &Handshake) -> Result<Handshake, UnicomError>{
+    let (code, id, size) = reader.read_head().await?;
+    match code{
+        0x43 => offer.negotiate(&Handshake::from_id(id)),
+        0 => {
+            reader.check_size(size)?;
+            Err(UnicomError::from_utf8(reader.read_body(size).await?)?)
+        },
+        _ => Err(UnicomError::new(UnicomErrorKind::ParseError, "Code security not 0x43")),
+    }
+}
 
+pub async fn read_message<R: AsyncRead + Unpin>(reader: &mut UnixReader<R>) -> Result<UnixMessage, UnicomError>{
+    let (kind, id, size) = reader.read_head().await?;
+    if let Err(error) = reader.check_size(size){
+        let origin = match kind{
+            1 => ChunkOrigin::Request,
+            3 => reader.peek().await?.try_into().unwrap_or(ChunkOrigin::Response),
+            _ => ChunkOrigin::Response,
+        };
+        reader.skip_body(size).await?;
+        return Ok(UnixMessage::Rejected { id, origin, error })
+    }
+    let body = match kind{
+        // frames without a body
+        4..=7 => {
+            reader.skip_body(size).await?;
+            Vec::new()
+        },
+        _ => reader.read_body(size).await?,
+    };
+    // the whole frame is consumed, a body that can not be decoded leaves the next frame readable
+    Ok(match decode_message(kind, id, body){
+        Ok(message) => message,
+        Err(error) => match kind{
+            0 => UnixMessage::Rejected { id, origin: ChunkOrigin::Response, error },
+            1 => UnixMessage::Rejected { id, origin: ChunkOrigin::Request, error },
+            _ => UnixMessage::Invalid { id, error },
+        },
+    })
 }
 
-pub async fn write_message(writer: &mut OwnedWriteHalf, message: UnixMessage) -> Result<(), UnicomError>{
-    match message {
-        UnixMessage::Response { id, data} => {
-            write_head(writer, 2, id, data.len()).await?;
-            write_body(writer, &data).await?;
-            Ok(())
+fn decode_message(kind: u8, id: u64, mut body: Vec<u8>) -> Result<UnixMessage, UnicomError>{
+    match kind {
+        0 => Ok(UnixMessage::Error{
+            id,
+            error: UnicomError::from_utf8(body)?,
+        }),
+        1 => Ok(UnixMessage::Request{
+            id,
+            data: UnicomRequest::from_utf8(body)?,
+        }),
+        2 => Ok(UnixMessage::Response{
+            id,
+            data: body,
+        }),
+        3 => {
+            if body.is_empty(){
+                return Err(UnicomError::new(UnicomErrorKind::DataInvalid, "chunk without origin"))
+            }
+            let origin = body.remove(0).try_into()?;
+            Ok(UnixMessage::Chunk { id, origin, data: body })
         },
-        UnixMessage::Request { id, data } => {
-            let json_data = match serde_json::to_string(&data){
-                Ok(data) => data.as_bytes().to_vec(),
-                Err(_) => return Err(UnicomError::new(UnicomErrorKind::ParseError, "json request invalid")),
-            };
-            write_head(writer, 1, id, json_data.len()).await?;
-            write_body(writer, &json_data).await?;
-            Ok(())
+        4 => Ok(UnixMessage::Quit),
+        5 => Ok(UnixMessage::Cancel { id }),
+        6 => Ok(UnixMessage::Ping { id }),
+        7 => Ok(UnixMessage::Pong { id }),
+        8 => {
+            if body.len() != 5{
+                return Err(UnicomError::new(UnicomErrorKind::DataInvalid, "files frame length error"))
+            }
+            let origin = body[0].try_into()?;
+            Ok(UnixMessage::Files { id, origin, count: LittleEndian::read_u32(&body[1..5]) as usize })
         },
-        UnixMessage::Quit => {
-            write_head(writer, 4, 0, 0).await?;
-            Ok(())
+        9 => {
+            let update = serde_json::from_slice(&body)
+                .map_err(|e| UnicomError::new(UnicomErrorKind::ParseError, &format!("read api update error {}", e)))?;
+            Ok(UnixMessage::Apis { update })
         },
-        UnixMessage::Error { id, error } => {
-            let json_data = match serde_json::to_string(&error){
-                Ok(data) => data.as_bytes().to_vec(),
-                Err(_) => return Err(UnicomError::new(UnicomErrorKind::ParseError, "json Error invalid")),
-            };
-            write_head(writer, 0, id, json_data.len()).await?;
-            write_body(writer, &json_data).await?;
-            Ok(())
+        10 => Ok(UnixMessage::Head{
+            id,
+            head: ResponseHead::from_utf8(body)?,
+        }),
+        11 => {
+            if body.len() != 5{
+                return Err(UnicomError::new(UnicomErrorKind::DataInvalid, "window frame length error"))
+            }
+            let origin = body[0].try_into()?;
+            Ok(UnixMessage::Window { id, origin, credit: LittleEndian::read_u32(&body[1..5]) })
         },
+        _ => Err(UnicomError::new(UnicomErrorKind::DataInvalid, &format!("kind message unknown {}", kind))),
     }
-}
\ No newline at end of file
+}
+
+pub async fn write_init<W: AsyncWrite + Unpin>(writer: &mut UnixWriter<W>, config: &NodeConfig, offer: &Handshake) -> Result<(), UnicomError>{
+    writer.write_json(0x42, offer.to_id(), config).await
+}
+
+/// Hub answer to a valid init frame with the negotiated version and capabilities
+pub async fn write_handshake<W: AsyncWrite + Unpin>(writer: &mut UnixWriter<W>, handshake: &Handshake) -> Result<(), UnicomError>{
+    writer.write_frame(0x43, handshake.to_id(), &[], &[]).await
+}
+
+pub async fn write_message<W: AsyncWrite + Unpin>(writer: &mut UnixWriter<W>, message: UnixMessage) -> Result<(), UnicomError>{
+    writer.queue_message(message).await?;
+    writer.flush().await
+}
@@ -0,0 +1,135 @@
+use std::os::fd::OwnedFd;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio::sync::{mpsc, OwnedSemaphorePermit, Semaphore};
+use tokio::task::JoinHandle;
+
+use crate::error::{UnicomError, UnicomErrorKind};
+use super::unix::{FdQueue, UnixMessage, UnixWriter};
+
+/// Bytes of small frames gathered before they are written in one go
+const COALESCE_SIZE: usize = 64 * 1024;
+
+/// Bytes of large bodies allowed to wait in the queue, a large frame waits for the ones before it to be
+/// written so a sender of large bodies can not run far ahead of the socket
+const QUEUED_BYTES: usize = 256 * 1024;
+
+/// Frame waiting for the writer task with the descriptors leaving next to it
+struct Outgoing{
+    message: UnixMessage,
+    files: Vec<OwnedFd>,
+    _budget: Option<OwnedSemaphorePermit>,
+}
+
+/// Queues frames for a writer task, cheap to clone
+#[derive(Clone)]
+pub struct FrameSender{
+    control: mpsc::Sender<Outgoing>,
+    data: mpsc::Sender<Outgoing>,
+    budget: Arc<Semaphore>,
+    priority: bool,
+}
+
+impl FrameSender{
+    /// Queue a frame, waits while the queue is full. A write failure is not reported here, it ends the
+    /// writer task and the connection
+    pub async fn send(&self, message: UnixMessage, files: Vec<OwnedFd>) -> Result<(), UnicomError>{
+        let size = match &message{
+            UnixMessage::Response { data, .. } | UnixMessage::Chunk { data, .. } => data.len(),
+            _ => 0,
+        };
+        if size >= u32::MAX as usize{
+            return Err(UnicomError::new(UnicomErrorKind::DataInvalid, "message body too large for one frame"))
+        }
+        let budget = match size > COALESCE_SIZE{
+            true => Some(self.budget.clone().acquire_many_owned(size.min(QUEUED_BYTES) as u32).await
+                .map_err(|_| UnicomError::new(UnicomErrorKind::LostConnection, "connection closed"))?),
+            false => None,
+        };
+        let sender = match message{
+            UnixMessage::Ping { .. } | UnixMessage::Pong { .. } if self.priority => &self.control,
+            _ => &self.data,
+        };
+        sender.send(Outgoing { message, files, _budget: budget }).await
+            .map_err(|_| UnicomError::new(UnicomErrorKind::LostConnection, "connection closed"))
+    }
+}
+
+/// Single owner of the writing half of a connection, every frame goes through its queue.
+///
+/// Frames ready at the same time are coalesced in one write. With `priority` pings and pongs skip
+/// the queue so a large response does not delay the heartbeat, other frames keep their order.
+pub struct WriterTask{
+    sender: FrameSender,
+    handle: JoinHandle<()>,
+}
+
+impl WriterTask{
+    /// Start writing to `writer`, `capacity` frames can wait in the queue. Descriptors of a frame
+    /// are pushed on `files` just before it is written
+    pub fn spawn<W>(writer: UnixWriter<W>, files: Option<FdQueue>, capacity: usize, priority: bool) -> WriterTask
+    where W: AsyncWrite + Send + Unpin + 'static{
+        let (control, control_receiver) = mpsc::channel(capacity.max(1));
+        let (data, data_receiver) = mpsc::channel(capacity.max(1));
+        let handle = tokio::spawn(async move {
+            if let Err(e) = write_frames(writer, control_receiver, data_receiver, files).await{
+                println!("error write frame {:?}", e);
+            }
+        });
+        let budget = Arc::new(Semaphore::new(QUEUED_BYTES));
+        WriterTask { sender: FrameSender { control, data, budget, priority }, handle }
+    }
+
+    pub fn sender(&self) -> FrameSender{
+        self.sender.clone()
+    }
+
+    /// Stop taking frames, write the queued ones and close the socket. The frames not written
+    /// within `timeout` are dropped
+    pub async fn close(self, timeout: Duration){
+        drop(self.sender);
+        let abort = self.handle.abort_handle();
+        if tokio::time::timeout(timeout, self.handle).await.is_err(){
+            println!("error close writer frames not written after {:?}", timeout);
+            abort.abort();
+        }
+    }
+}
+
+async fn write_frames<W>(mut writer: UnixWriter<W>, mut control: mpsc::Receiver<Outgoing>, mut data: mpsc::Receiver<Outgoing>, files: Option<FdQueue>) -> Result<(), UnicomError>
+where W: AsyncWrite + Unpin{
+    loop{
+        let first = tokio::select!{
+            biased;
+            Some(frame) = control.recv() => frame,
+            Some(frame) = data.recv() => frame,
+            else => break,
+        };
+        let mut next = Some(first);
+        while let Some(frame) = next{
+            // descriptors ride on the next write, a frame carrying some is written on its own so one
+            // write never holds the descriptors of several frames, more than the peer accepts at once
+            let alone = !frame.files.is_empty();
+            if alone{
+                if writer.buffered() > 0{
+                    writer.flush().await?;
+                }
+                match &files{
+                    Some(files) => files.lock().unwrap().extend(frame.files),
+                    None => return Err(UnicomError::new(UnicomErrorKind::NotAllowed, "transport can not pass files")),
+                }
+            }
+            // the budget of the frame is given back once its body left the queue
+            writer.queue_message(frame.message).await?;
+            next = match !alone && writer.buffered() < COALESCE_SIZE{
+                true => control.try_recv().or_else(|_| data.try_recv()).ok(),
+                false => None,
+            };
+        }
+        writer.flush().await?;
+    }
+    writer.into_inner().shutdown().await?;
+    Ok(())
+}
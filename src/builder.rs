@@ -0,0 +1,172 @@
+use std::{path::{Path, PathBuf}, sync::Arc};
+
+use crate::arch::Address;
+use crate::arch::unix::Capabilities;
+use crate::config::{Config, ConnectionConfig, Manifest};
+use crate::error::{UnicomError, UnicomErrorKind};
+use crate::node::{NodeConfig, utils::limit::ApiOptions};
+use crate::middleware::UnicomMiddleware;
+use crate::{ServerConnection, UnicomApi};
+
+/// Default location of the unicom config, read by `ServerConnection::new`
+pub const DEFAULT_CONFIG_PATH: &str = "/etc/unicom/config.toml";
+
+enum ConfigSource{
+    Path(PathBuf),
+    Config(Box<Config>),
+}
+
+enum NodeSource{
+    Path(PathBuf),
+    Manifest(Manifest),
+    Config(NodeConfig),
+}
+
+/// Builds a `ServerConnection` from explicit sources, every failure is returned instead of panicking.
+///
+/// The address comes from `address` or else from the config, the connection limits from `connection`
+/// or else from the config, the node from a manifest or a `NodeConfig`.
+///
+/// ```no_run
+/// # fn build() -> Result<(), unicom_lib::error::UnicomError>{
+/// use unicom_lib::{ServerConnection, config::Manifest};
+///
+/// let server = ServerConnection::builder()
+///     .address("unix:///run/unicom.sock".parse()?)
+///     .manifest(Manifest::new("my_node"))
+///     .build()?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Default)]
+pub struct ServerConnectionBuilder{
+    address: Option<Address>,
+    config: Option<ConfigSource>,
+    node: Option<NodeSource>,
+    connection: Option<ConnectionConfig>,
+    capabilities: Option<Capabilities>,
+    apis: Vec<(Arc<dyn UnicomApi>, ApiOptions)>,
+    middleware: Vec<Arc<dyn UnicomMiddleware>>,
+}
+
+impl ServerConnectionBuilder{
+    pub fn new() -> ServerConnectionBuilder{
+        ServerConnectionBuilder::default()
+    }
+
+    /// Hub address, takes precedence over the one of the config
+    pub fn address(mut self, address: Address) -> ServerConnectionBuilder{
+        self.address = Some(address);
+        self
+    }
+
+    /// Read the unicom config from `path` when building
+    pub fn config_path<P: AsRef<Path>>(mut self, path: P) -> ServerConnectionBuilder{
+        self.config = Some(ConfigSource::Path(path.as_ref().to_path_buf()));
+        self
+    }
+
+    pub fn config(mut self, config: Config) -> ServerConnectionBuilder{
+        self.config = Some(ConfigSource::Config(Box::new(config)));
+        self
+    }
+
+    /// Read the node manifest from `path` when building
+    pub fn manifest_path<P: AsRef<Path>>(mut self, path: P) -> ServerConnectionBuilder{
+        self.node = Some(NodeSource::Path(path.as_ref().to_path_buf()));
+        self
+    }
+
+    pub fn manifest(mut self, manifest: Manifest) -> ServerConnectionBuilder{
+        self.node = Some(NodeSource::Manifest(manifest));
+        self
+    }
+
+    /// Node sent to the hub as is, the apis added to the connection are appended to it
+    pub fn node_config(mut self, config: NodeConfig) -> ServerConnectionBuilder{
+        self.node = Some(NodeSource::Config(config));
+        self
+    }
+
+    /// Connection limits, take precedence over the ones of the config
+    pub fn connection(mut self, connection: ConnectionConfig) -> ServerConnectionBuilder{
+        self.connection = Some(connection);
+        self
+    }
+
+    pub fn capabilities(mut self, capabilities: Capabilities) -> ServerConnectionBuilder{
+        self.capabilities = Some(capabilities);
+        self
+    }
+
+    pub fn api(self, api: Arc<dyn UnicomApi>) -> ServerConnectionBuilder{
+        self.api_with_options(api, ApiOptions::default())
+    }
+
+    /// Run `middleware` around the handlers of every api, in the order they are added
+    pub fn middleware(mut self, middleware: Arc<dyn UnicomMiddleware>) -> ServerConnectionBuilder{
+        self.middleware.push(middleware);
+        self
+    }
+
+    /// Add an api with concurrency limits or its own middleware
+    pub fn api_with_options(mut self, api: Arc<dyn UnicomApi>, options: ApiOptions) -> ServerConnectionBuilder{
+        self.apis.push((api, options));
+        self
+    }
+
+    pub fn build(self) -> Result<ServerConnection, UnicomError>{
+        let config = match self.config{
+            Some(ConfigSource::Path(path)) => Some(read_toml::<Config>(&path)?),
+            Some(ConfigSource::Config(config)) => Some(*config),
+            None => None,
+        };
+
+        let address = match (self.address, &config){
+            (Some(address), _) => address,
+            (None, Some(config)) => config.address()?,
+            (None, None) => return Err(UnicomError::new(UnicomErrorKind::MandatoryMissing, "no hub address nor config given")),
+        };
+
+        let connection = match (self.connection, config){
+            (Some(connection), _) => connection,
+            (None, Some(config)) => config.connection,
+            (None, None) => ConnectionConfig::default(),
+        };
+
+        let node = match self.node{
+            Some(NodeSource::Path(path)) => manifest_to_node(read_toml::<Manifest>(&path)?)?,
+            Some(NodeSource::Manifest(manifest)) => manifest_to_node(manifest)?,
+            Some(NodeSource::Config(config)) => config,
+            None => return Err(UnicomError::new(UnicomErrorKind::MandatoryMissing, "no manifest nor node config given")),
+        };
+
+        for (_, options) in &self.apis{
+            options.check()?;
+        }
+
+        let mut server = ServerConnection::from_node_config(address, node);
+        server.set_connection_config(connection);
+        if let Some(capabilities) = self.capabilities{
+            server.set_capabilities(capabilities);
+        }
+        for middleware in self.middleware{
+            server.add_middleware(middleware);
+        }
+        for (api, options) in self.apis{
+            server.add_api_with_options(api, options);
+        }
+        Ok(server)
+    }
+}
+
+fn read_toml<T: serde::de::DeserializeOwned>(path: &Path) -> Result<T, UnicomError>{
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| UnicomError::new(e.kind().into(), &format!("read {} error {}", path.display(), e)))?;
+    toml::from_str(&content)
+        .map_err(|e| UnicomError::new(UnicomErrorKind::ParseError, &format!("parse {} error {}", path.display(), e)))
+}
+
+fn manifest_to_node(manifest: Manifest) -> Result<NodeConfig, UnicomError>{
+    manifest.try_into().map_err(|e: String| UnicomError::new(UnicomErrorKind::ParseError, &format!("manifest error {}", e)))
+}
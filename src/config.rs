@@ -1,5 +1,9 @@
-use std::{collections::HashMap, path::{Path, PathBuf}, fs::canonicalize};
+use std::{collections::HashMap, path::{Path, PathBuf}, fs::canonicalize, time::Duration};
+use crate::arch::{Address, unix::PeerCredentials};
+use crate::error::{UnicomError, UnicomErrorKind};
+use crate::UserLevel;
 use crate::node::{endpoint::{ApiConfig, EndPointKind}, NodeConfig};
+use rand::Rng;
 use walkdir::WalkDir;
 
 #[derive(Debug, Deserialize)]
@@ -10,8 +14,153 @@ pub struct Config {
     pub app_dir: String,
     pub session_path: String,
     pub framwork_path: String,
+    /// Hub address for nodes, `unix://` or `tcp://`, defaults to the unix stream path
+    #[serde(default)]
+    pub address: Option<String>,
+    #[serde(default)]
+    pub connection: ConnectionConfig,
+    /// Which local users may register which nodes on the unix socket
+    #[serde(default)]
+    pub peers: PeerPolicy,
 }
 
+impl Config{
+    pub fn address(&self) -> Result<Address, UnicomError>{
+        match &self.address{
+            Some(address) => address.parse(),
+            None => Ok(Address::Unix(PathBuf::from(&self.unix_stream_path))),
+        }
+    }
+}
+
+/// Limits applied to every node connection, all fields are optional in the config file
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct ConnectionConfig{
+    /// Largest frame body accepted from a peer, in bytes
+    pub max_frame_size: usize,
+    /// Outgoing frames allowed to wait for the socket at the same time
+    pub max_pending_writes: usize,
+    /// Let pings and pongs overtake the queued frames so large writes do not trip the heartbeat
+    pub prioritize_control: bool,
+    /// Time between two pings in milliseconds, 0 disables the heartbeat
+    pub heartbeat_interval: u64,
+    /// Unanswered pings after which the peer is considered dead, 0 disables the heartbeat
+    pub heartbeat_misses: u32,
+    /// Time a request waits for its answer in milliseconds, 0 waits forever
+    pub request_timeout: u64,
+    /// Time the node waits for the hub to answer its init frame in milliseconds, 0 waits forever
+    pub handshake_timeout: u64,
+    /// Reconnection to a hub that went away, without it the node stops with the connection
+    pub reconnect: Option<ReconnectPolicy>,
+}
+
+impl Default for ConnectionConfig{
+    fn default() -> Self {
+        ConnectionConfig {
+            max_frame_size: 64 * 1024 * 1024,
+            max_pending_writes: 64,
+            prioritize_control: true,
+            heartbeat_interval: 5000,
+            heartbeat_misses: 3,
+            request_timeout: 0,
+            handshake_timeout: 10000,
+            reconnect: None,
+        }
+    }
+}
+
+/// Delays between reconnection attempts, doubled after each failure up to `max_delay`
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct ReconnectPolicy{
+    /// Delay before the first attempt in milliseconds
+    pub initial_delay: u64,
+    /// Longest delay between two attempts in milliseconds
+    pub max_delay: u64,
+    /// Attempts before giving up, 0 retries forever
+    pub max_attempts: u32,
+}
+
+impl Default for ReconnectPolicy{
+    fn default() -> Self {
+        ReconnectPolicy {
+            initial_delay: 100,
+            max_delay: 30_000,
+            max_attempts: 0,
+        }
+    }
+}
+
+impl ReconnectPolicy{
+    /// Delay before attempt `attempt`, counted from 0. Half of it is random so nodes restarted
+    /// together do not all hit the hub at the same time
+    pub fn delay(&self, attempt: u32) -> Duration{
+        let delay = self.initial_delay.saturating_mul(1u64 << attempt.min(32)).min(self.max_delay);
+        let half = delay / 2;
+        Duration::from_millis(half + rand::thread_rng().gen_range(0..=delay - half))
+    }
+}
+
+/// Rule of the peer policy, matches a uid, a gid or both
+#[derive(Debug, Deserialize, Clone)]
+pub struct PeerRule{
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    /// Node names the peer may register, `*` allows any name
+    pub nodes: Vec<String>,
+    pub level: UserLevel,
+}
+
+impl PeerRule{
+    fn matches(&self, credentials: &PeerCredentials) -> bool{
+        if self.uid.is_none() && self.gid.is_none(){
+            return false
+        }
+        self.uid.is_none_or(|uid| uid == credentials.uid) && self.gid.is_none_or(|gid| gid == credentials.gid)
+    }
+
+    fn allows(&self, node: &str) -> bool{
+        self.nodes.iter().any(|name| name == "*" || name == node)
+    }
+}
+
+/// Checks node registrations against the credentials of the connecting process.
+///
+/// ```toml
+/// [peers]
+/// strict = true
+///
+/// [[peers.rules]]
+/// uid = 1000
+/// nodes = ["media", "torrent"]
+/// level = "Normal"
+/// ```
+#[derive(Debug, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct PeerPolicy{
+    /// Refuse peers matched by no rule, or without credentials like tcp peers, instead of registering them as `Normal`
+    pub strict: bool,
+    /// Checked in order, the first rule matching the peer decides
+    pub rules: Vec<PeerRule>,
+}
+
+impl PeerPolicy{
+    /// Level granted to a peer registering `node`, `NotAllowed` when the policy refuses it
+    pub fn authorize(&self, credentials: Option<&PeerCredentials>, node: &str) -> Result<UserLevel, UnicomError>{
+        let rule = credentials.and_then(|credentials| self.rules.iter().find(|rule| rule.matches(credentials)));
+        let peer = match credentials{
+            Some(credentials) => format!("uid {} gid {}", credentials.uid, credentials.gid),
+            None => "without credentials".to_string(),
+        };
+        match rule{
+            Some(rule) if rule.allows(node) => Ok(rule.level.clone()),
+            Some(_) => Err(UnicomError::new(UnicomErrorKind::NotAllowed, &format!("peer {} may not register node {}", peer, node))),
+            None if self.strict => Err(UnicomError::new(UnicomErrorKind::NotAllowed, &format!("peer {} unknown, node {} refused", peer, node))),
+            None => Ok(UserLevel::Normal),
+        }
+    }
+}
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct ManifestTag{
@@ -36,14 +185,26 @@ pub struct Manifest{
     endpoints: Option<Vec<ManifestEndpoint>>,
 }
 
+impl Manifest{
+    /// Manifest of a node without templates, tags or endpoints
+    pub fn new(name: &str) -> Manifest{
+        Manifest {
+            name: name.to_owned(),
+            templates: None,
+            tags: None,
+            endpoints: None,
+        }
+    }
+}
+
 impl TryInto<NodeConfig> for Manifest{
     type Error = String;
 
     fn try_into(self) -> Result<NodeConfig, Self::Error> {
         let mut config = NodeConfig::new(&self.name);
-        if self.templates.is_some(){
-            let size = self.templates.as_ref().unwrap().len();
-            for entry in WalkDir::new(self.templates.unwrap())
+        if let Some(templates) = self.templates{
+            let size = templates.len();
+            for entry in WalkDir::new(templates)
                     .follow_links(true)
                     .into_iter()
                     .filter_map(|e| e.ok()) {
@@ -51,12 +212,13 @@ impl TryInto<NodeConfig> for Manifest{
                 if !entry.file_type().is_file(){
                     continue
                 }
-                let (_, name) = entry.path().to_str().unwrap().split_at(size+1);
+                let path = entry.path().to_str().ok_or_else(|| format!("Template path not utf8 {:?}", entry.path()))?;
+                let (_, name) = path.split_at(size+1);
                 let terra_path = Path::new(&self.name).join(name);
-                let absolute_path = entry.path().canonicalize().unwrap();
+                let absolute_path = entry.path().canonicalize().map_err(|e| format!("Template {} error {}", path, e))?;
 
                 println!("name: {}, terra {:?}", self.name, terra_path);
-                config.add_template(absolute_path.to_str().unwrap(), terra_path.to_str().unwrap());
+                config.add_template(&absolute_path.to_string_lossy(), &terra_path.to_string_lossy());
                 
             }
         }
@@ -72,8 +234,8 @@ impl TryInto<NodeConfig> for Manifest{
                         }
                         let path = endpoint.path.unwrap();
                         let srcdir = PathBuf::from(&path);
-                        let full = canonicalize(&srcdir).expect(&format!("Static file/directory {} Not Found", path));
-                        let full_path = full.to_str().unwrap().to_string();
+                        let full = canonicalize(&srcdir).map_err(|_| format!("Static file/directory {} Not Found", path))?;
+                        let full_path = full.to_string_lossy().to_string();
                         EndPointKind::Static{path: full_path}
                     },
                     "rest" => {
@@ -0,0 +1,118 @@
+use std::{any::{Any, TypeId}, collections::HashMap, sync::{Arc, atomic::{AtomicBool, Ordering}}, time::{Duration, Instant}};
+
+use tokio::sync::Notify;
+
+use crate::User;
+use crate::node::{api::MethodKind, message::request::UnicomRequest};
+
+/// What a handler knows about the request it answers, built by `ServerConnection::run` when the request arrives
+pub struct RequestContext{
+    /// Frame id of the request on the connection to the hub
+    pub id: u64,
+    pub method: MethodKind,
+    /// Node that sent the request, when the hub tells it
+    pub caller: Option<String>,
+    /// User authenticated by the hub, when the hub tells it
+    pub user: Option<User>,
+    /// Id following the request across nodes, when the caller gave one
+    pub trace_id: Option<String>,
+    pub received: Instant,
+    pub deadline: Option<Instant>,
+    pub cancellation: Cancellation,
+    /// Values set by middleware for the handlers after them
+    pub extensions: Extensions,
+}
+
+impl RequestContext{
+    pub fn new(id: u64, request: &UnicomRequest) -> RequestContext{
+        RequestContext {
+            id,
+            method: request.method.clone(),
+            caller: request.caller.clone(),
+            user: request.user.clone(),
+            trace_id: request.trace_id.clone(),
+            received: Instant::now(),
+            deadline: request.deadline(),
+            cancellation: Cancellation::new(),
+            extensions: Extensions::default(),
+        }
+    }
+
+    /// Time left to answer before the caller gives up, `None` when it waits forever
+    pub fn remaining(&self) -> Option<Duration>{
+        self.deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()))
+    }
+
+    pub fn is_cancelled(&self) -> bool{
+        self.cancellation.is_cancelled()
+    }
+}
+
+/// Set when the caller cancels the request or the connection to the hub is lost, cheap to clone.
+///
+/// The handler itself is stopped at its next await, the signal is for the work it hands to other tasks.
+#[derive(Clone, Default)]
+pub struct Cancellation{
+    state: Arc<CancellationState>,
+}
+
+#[derive(Default)]
+struct CancellationState{
+    cancelled: AtomicBool,
+    notify: Notify,
+}
+
+impl Cancellation{
+    pub fn new() -> Cancellation{
+        Cancellation::default()
+    }
+
+    pub fn cancel(&self){
+        self.state.cancelled.store(true, Ordering::Release);
+        self.state.notify.notify_waiters();
+    }
+
+    pub fn is_cancelled(&self) -> bool{
+        self.state.cancelled.load(Ordering::Acquire)
+    }
+
+    /// Wait until the request is cancelled
+    pub async fn cancelled(&self){
+        loop{
+            let notified = self.state.notify.notified();
+            tokio::pin!(notified);
+            notified.as_mut().enable();
+            if self.is_cancelled(){
+                return
+            }
+            notified.await;
+        }
+    }
+}
+
+/// Values of any type, one per type
+#[derive(Default)]
+pub struct Extensions{
+    map: HashMap<TypeId, Box<dyn Any + Send + Sync>>,
+}
+
+impl Extensions{
+    /// Store `value`, returns the value of the same type it replaces
+    pub fn insert<T: Any + Send + Sync>(&mut self, value: T) -> Option<T>{
+        self.map.insert(TypeId::of::<T>(), Box::new(value))
+            .and_then(|previous| previous.downcast().ok().map(|previous| *previous))
+    }
+
+    pub fn get<T: Any + Send + Sync>(&self) -> Option<&T>{
+        self.map.get(&TypeId::of::<T>()).and_then(|value| value.downcast_ref())
+    }
+
+    pub fn get_mut<T: Any + Send + Sync>(&mut self) -> Option<&mut T>{
+        self.map.get_mut(&TypeId::of::<T>()).and_then(|value| value.downcast_mut())
+    }
+
+    pub fn remove<T: Any + Send + Sync>(&mut self) -> Option<T>{
+        self.map.remove(&TypeId::of::<T>())
+            .and_then(|value| value.downcast().ok().map(|value| *value))
+    }
+}
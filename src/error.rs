@@ -21,6 +21,8 @@ pub enum UnicomErrorKind{
     MethodNotAllowed,
     OutOfMemory,
     RenderFailed,
+    VersionMismatch,
+    Busy,
 }
 
 impl From<ErrorKind> for UnicomErrorKind{
@@ -48,9 +50,9 @@ impl From<ErrorKind> for UnicomErrorKind{
     }
 }
 
-impl Into<StatusCode> for UnicomErrorKind{
-    fn into(self) -> StatusCode {
-        match self{
+impl From<UnicomErrorKind> for StatusCode{
+    fn from(kind: UnicomErrorKind) -> Self {
+        match kind{
             UnicomErrorKind::NotFound => StatusCode::NOT_FOUND,
             UnicomErrorKind::Unknown => StatusCode::NO_CONTENT,
             UnicomErrorKind::ParameterInvalid => StatusCode::BAD_REQUEST,
@@ -67,6 +69,8 @@ impl Into<StatusCode> for UnicomErrorKind{
             UnicomErrorKind::NotAllowed => StatusCode::FORBIDDEN,
             UnicomErrorKind::OutOfMemory => StatusCode::INTERNAL_SERVER_ERROR,
             UnicomErrorKind::RenderFailed => StatusCode::INTERNAL_SERVER_ERROR,
+            UnicomErrorKind::VersionMismatch => StatusCode::BAD_GATEWAY,
+            UnicomErrorKind::Busy => StatusCode::SERVICE_UNAVAILABLE,
         }
     }
 }
@@ -86,6 +90,10 @@ impl UnicomError{
         }
     }
 
+    pub fn kind(&self) -> &UnicomErrorKind{
+        &self.kind
+    }
+
     pub fn from_utf8(message: Vec<u8>) -> Result<UnicomError, UnicomError>{
         Ok(serde_json::from_str(&String::from_utf8(message)?)?)
     }
@@ -145,12 +153,12 @@ impl From<rusqlite::Error> for UnicomError{
     }
 }
 
-impl Into<Response<Body>> for UnicomError{
-    fn into(self) -> Response<Body> {
-        let status: StatusCode = self.kind.into();
+impl From<UnicomError> for Response<Body>{
+    fn from(error: UnicomError) -> Self {
+        let status: StatusCode = error.kind.into();
         Response::builder()
         .status(status)
-        .body(Body::from(self.description)).unwrap()
+        .body(Body::from(error.description)).unwrap()
     }
     
 }
\ No newline at end of file
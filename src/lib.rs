@@ -5,161 +5,1075 @@ pub mod node;
 pub mod error;
 pub mod arch;
 pub mod config;
+pub mod testing;
+pub mod builder;
+pub mod middleware;
+pub mod context;
+pub mod router;
 
+pub use node::api::UnicomParams;
+pub use unicom_lib_derive::{UnicomParams, unicom_api};
+// used by the code of the macros
+#[doc(hidden)]
+pub use async_trait::async_trait;
+#[doc(hidden)]
+pub use serde_json;
 
-use std::{sync::Arc, collections::HashMap};
 
-use arch::unix::{write_init, UnixMessage, write_message, read_message};
-use async_trait::async_trait;
-use config::{Manifest, Config};
+use std::{any::Any, panic::AssertUnwindSafe, sync::{Arc, RwLock, atomic::{AtomicBool, AtomicU64, Ordering}}, collections::HashMap, fs::File, os::fd::OwnedFd, time::Duration};
+
+use builder::{ServerConnectionBuilder, DEFAULT_CONFIG_PATH};
+use context::{Cancellation, RequestContext};
+use middleware::{Next, UnicomMiddleware};
+use arch::{Address, BoxReader, Connection, FdChannel, writer::WriterTask};
+use arch::unix::{write_init, UnixMessage, read_message, read_handshake, ChunkOrigin, Capabilities, Handshake, UnixReader, UnixWriter, CHUNK_SIZE, INITIAL_WINDOW, MAX_FILES};
+use bytes::Bytes;
+use config::{Manifest, ConnectionConfig};
 use error::{UnicomError, UnicomErrorKind};
-use node::{api::{ApiMethod, MethodKind}, message::request::UnicomRequest, utils::pending::PendingController, NodeConfig};
+use futures::{FutureExt, StreamExt};
+use node::{api::{options_body, Api, ApiMethod, ApiUpdate}, message::{request::UnicomRequest, response::{ResponseHead, UnicomResponse}, stream::{end_body, push_body, BodyStream, BodySender, UnicomStream, WindowSender}}, utils::{pending::{PendingController, PendingGuard}, limit::{ApiLimit, ApiOptions, Admission}}, NodeConfig};
+use serde::{de::DeserializeOwned, Serialize};
 use serde_json::{Map, Value};
-use tokio::{sync::{Mutex, Notify}, net::{unix::{OwnedWriteHalf, OwnedReadHalf}, UnixStream}};
+use tokio::{sync::{mpsc, Mutex, Notify, Semaphore}, task::AbortHandle};
 
 #[async_trait]
 pub trait UnicomApi: Sync + Send {
     fn name(&self) -> String;
     fn description(&self) -> Vec<ApiMethod>;
-    async fn api_get(&self, server: &Arc<ServerConnection>, request: &UnicomRequest) -> Result<Vec<u8>, UnicomError>;
-    async fn api_put(&self, server: &Arc<ServerConnection>, request: &UnicomRequest) -> Result<Vec<u8>, UnicomError>;
-    async fn api_post(&self, server: &Arc<ServerConnection>, request: &UnicomRequest) -> Result<Vec<u8>, UnicomError>;
-    async fn api_delete(&self, server: &Arc<ServerConnection>, request: &UnicomRequest) -> Result<Vec<u8>, UnicomError>;
+    async fn api_get(&self, server: &Arc<ServerConnection>, request: &UnicomRequest, context: &RequestContext) -> Result<Vec<u8>, UnicomError>;
+    async fn api_put(&self, server: &Arc<ServerConnection>, request: &UnicomRequest, context: &RequestContext) -> Result<Vec<u8>, UnicomError>;
+    async fn api_post(&self, server: &Arc<ServerConnection>, request: &UnicomRequest, context: &RequestContext) -> Result<Vec<u8>, UnicomError>;
+    async fn api_delete(&self, server: &Arc<ServerConnection>, request: &UnicomRequest, context: &RequestContext) -> Result<Vec<u8>, UnicomError>;
+
+    async fn api_patch(&self, _server: &Arc<ServerConnection>, _request: &UnicomRequest, _context: &RequestContext) -> Result<Vec<u8>, UnicomError>{
+        Err(UnicomError::new(UnicomErrorKind::MethodNotAllowed, &format!("method PATCH not allowed on {}", self.name())))
+    }
+
+    /// Runs GET and drops the body
+    async fn api_head(&self, server: &Arc<ServerConnection>, request: &UnicomRequest, context: &RequestContext) -> Result<Vec<u8>, UnicomError>{
+        self.api_get(server, request, context).await.map(|_| Vec::new())
+    }
+
+    /// Answers the methods of `description` with their parameters
+    async fn api_options(&self, _server: &Arc<ServerConnection>, _request: &UnicomRequest, _context: &RequestContext) -> Result<Vec<u8>, UnicomError>{
+        options_body(&self.description())
+    }
+
+    /// Answer with a body streamed in chunks instead of calling the method handlers, `None` keeps the regular dispatch.
+    /// Not called for HEAD and OPTIONS
+    async fn api_stream(&self, _server: &Arc<ServerConnection>, _request: &UnicomRequest, _context: &RequestContext) -> Result<Option<BodyStream>, UnicomError>{
+        Ok(None)
+    }
 }
 
 pub struct ServerConnection{
-    stream_path: String,
-    api: HashMap<u16, Arc<dyn UnicomApi>>,
-    manifest: Manifest,
-    counter: u16,
-    writer: Mutex<Option<OwnedWriteHalf>>,
+    address: Address,
+    api: RwLock<HashMap<u64, Arc<ApiEntry>>>,
+    middleware: Vec<Arc<dyn UnicomMiddleware>>,
+    node: NodeConfig,
+    counter: AtomicU64,
+    writer: Mutex<Option<WriterTask>>,
+    incoming: Mutex<HashMap<u64, BodySender>>,
+    capabilities: Capabilities,
+    handshake: Mutex<Handshake>,
+    connection: ConnectionConfig,
+    cancel: mpsc::UnboundedSender<u64>,
+    cancelled: Mutex<Option<mpsc::UnboundedReceiver<u64>>>,
+    running: Mutex<HashMap<u64, (AbortHandle, Cancellation)>>,
+    /// Credit left for each body being sent, with flow control
+    windows: std::sync::Mutex<HashMap<(u64, ChunkOrigin), Arc<Semaphore>>>,
+    window: WindowSender,
+    granted: Mutex<Option<mpsc::UnboundedReceiver<(u64, ChunkOrigin, u32)>>>,
+    last_pong: AtomicU64,
+    files: Mutex<Option<FdChannel>>,
+    incoming_files: Mutex<HashMap<u64, Vec<File>>>,
+    disconnected: Option<DisconnectedHook>,
+    reconnected: Option<ReconnectedHook>,
+    panicked: Option<PanicHook>,
+    closing: AtomicBool,
+    idle: Notify,
+    stop: Notify,
     pub pending: PendingController,
 }
 
+struct ApiEntry{
+    handler: Arc<dyn UnicomApi>,
+    limit: Option<ApiLimit>,
+    middleware: Vec<Arc<dyn UnicomMiddleware>>,
+}
+
+type DisconnectedHook = Box<dyn Fn(&UnicomError) + Send + Sync>;
+type ReconnectedHook = Box<dyn Fn(&Handshake) + Send + Sync>;
+type PanicHook = Box<dyn Fn(&UnicomRequest, &str) + Send + Sync>;
+
+/// Credit left to send chunks of one body, forgotten once the body is sent or dropped
+struct Credit<'a>{
+    windows: &'a std::sync::Mutex<HashMap<(u64, ChunkOrigin), Arc<Semaphore>>>,
+    key: (u64, ChunkOrigin),
+    permits: Arc<Semaphore>,
+}
+
+impl Credit<'_>{
+    fn open(windows: &std::sync::Mutex<HashMap<(u64, ChunkOrigin), Arc<Semaphore>>>, id: u64, origin: ChunkOrigin) -> Credit<'_>{
+        let permits = Arc::new(Semaphore::new(INITIAL_WINDOW));
+        windows.lock().unwrap().insert((id, origin), permits.clone());
+        Credit { windows, key: (id, origin), permits }
+    }
+
+    /// Wait until the receiver takes one more chunk, fails once the connection is lost
+    async fn take(&self) -> Result<(), UnicomError>{
+        let permit = self.permits.acquire().await
+            .map_err(|_| UnicomError::new(UnicomErrorKind::LostConnection, "connection closed"))?;
+        permit.forget();
+        Ok(())
+    }
+}
+
+impl Drop for Credit<'_>{
+    fn drop(&mut self) {
+        let mut windows = self.windows.lock().unwrap();
+        if windows.get(&self.key).is_some_and(|permits| Arc::ptr_eq(permits, &self.permits)){
+            windows.remove(&self.key);
+        }
+    }
+}
+
+/// Why the read loop stopped
+enum Disconnect{
+    /// The hub asked the node to stop or refused its config, it is not reconnected
+    Closed(UnicomError),
+    Lost(UnicomError),
+}
+
 impl ServerConnection{
+    /// Connection configured from `/etc/unicom/config.toml`, or the hub address `path`, and `manifest.toml`
+    /// of the current directory. Panics on any failure, `builder` returns errors instead
     pub fn new(path: Option<&str>) -> ServerConnection{
-        let stream_path: String;
-        if path.is_none(){
-            let content = std::fs::read_to_string("/etc/unicom/config.toml").expect("Failed to read unicom config");
-            let config: Config = toml::from_str(&content).expect("Failed to parse unicom config");
-            stream_path = config.unix_stream_path.clone();
-        }
-        else{
-            stream_path = path.unwrap().to_string();
-        }
+        let builder = match path{
+            Some(path) => ServerConnection::builder().address(path.parse().expect("Failed to parse unicom address")),
+            None => ServerConnection::builder().config_path(DEFAULT_CONFIG_PATH),
+        };
+        builder.manifest_path("manifest.toml").build().expect("Failed to build unicom connection")
+    }
+
+    pub fn builder() -> ServerConnectionBuilder{
+        ServerConnectionBuilder::new()
+    }
 
-        let content = std::fs::read_to_string("manifest.toml").expect("Failed to read manifest");
-        let manifest: Manifest = toml::from_str(&content).expect("Failed to parse manifest");
-        
+    /// Connection to the hub at `address` without reading any config or manifest file
+    pub fn from_manifest(address: Address, manifest: Manifest) -> Result<ServerConnection, UnicomError>{
+        ServerConnection::builder().address(address).manifest(manifest).build()
+    }
+
+    /// Connection registering `node` and the apis added later to the hub at `address`
+    pub fn from_node_config(address: Address, node: NodeConfig) -> ServerConnection{
+        let connection = ConnectionConfig::default();
+        let (cancel, cancelled) = mpsc::unbounded_channel();
+        let (window, granted) = mpsc::unbounded_channel();
 
         ServerConnection { 
-            stream_path, 
-            api: HashMap::new(),
-            counter: 0, 
-            manifest,
+            address, 
+            api: RwLock::new(HashMap::new()),
+            middleware: Vec::new(),
+            counter: AtomicU64::new(0),
+            node,
             writer: Mutex::new(None) ,
+            incoming: Mutex::new(HashMap::new()),
+            capabilities: Capabilities::supported(),
+            handshake: Mutex::new(Handshake::new(Capabilities::empty())),
+            connection,
+            cancel,
+            cancelled: Mutex::new(Some(cancelled)),
+            running: Mutex::new(HashMap::new()),
+            windows: std::sync::Mutex::new(HashMap::new()),
+            window,
+            granted: Mutex::new(Some(granted)),
+            last_pong: AtomicU64::new(0),
+            files: Mutex::new(None),
+            incoming_files: Mutex::new(HashMap::new()),
+            disconnected: None,
+            reconnected: None,
+            panicked: None,
+            closing: AtomicBool::new(false),
+            idle: Notify::new(),
+            stop: Notify::new(),
             pending: PendingController::new(),
         }
     }
 
     pub fn add_api(&mut self, api: Arc<dyn UnicomApi>){
-        self.api.insert(self.counter, api);
-        self.counter += 1;
+        self.add_api_with_options(api, ApiOptions::default());
+    }
+
+    /// Add an api running at most `options.max_concurrency` requests at once, see `ApiOptions`.
+    /// Panics when `options.max_concurrency` is 0, `builder` returns the error instead
+    pub fn add_api_with_options(&mut self, api: Arc<dyn UnicomApi>, options: ApiOptions){
+        options.check().expect("Invalid api options");
+        let id = self.counter.fetch_add(1, Ordering::Relaxed);
+        self.api.get_mut().unwrap().insert(id, ServerConnection::api_entry(api, options));
+    }
+
+    fn api_entry(api: Arc<dyn UnicomApi>, options: ApiOptions) -> Arc<ApiEntry>{
+        Arc::new(ApiEntry { limit: ApiLimit::new(&options), handler: api, middleware: options.middleware })
+    }
+
+    fn find_api(&self, id: u64) -> Option<Arc<ApiEntry>>{
+        self.api.read().unwrap().get(&id).cloned()
+    }
+
+    /// Whether api updates can be sent now, registering while disconnected is sent with the next init
+    async fn api_updates(&self) -> Result<bool, UnicomError>{
+        if self.writer.lock().await.is_none(){
+            return Ok(false)
+        }
+        if !self.handshake().await.capabilities.contains(Capabilities::DYNAMIC_APIS){
+            return Err(UnicomError::new(UnicomErrorKind::NotAllowed, "hub does not take api updates"))
+        }
+        Ok(true)
+    }
+
+    /// Add an api while the node runs, the hub is told to route requests to it. Returns the api id,
+    /// a name already registered is refused with `NotAllowed`
+    pub async fn register_api(&self, api: Arc<dyn UnicomApi>, options: ApiOptions) -> Result<u64, UnicomError>{
+        options.check()?;
+        let connected = self.api_updates().await?;
+        let name = api.name();
+        let (id, update) = {
+            let mut apis = self.api.write().unwrap();
+            if apis.values().any(|entry| entry.handler.name() == name){
+                return Err(UnicomError::new(UnicomErrorKind::NotAllowed, &format!("api {} already registered", name)))
+            }
+            let id = self.counter.fetch_add(1, Ordering::Relaxed);
+            let update = ApiUpdate::Added(Api::new(id, &name, api.description()));
+            apis.insert(id, ServerConnection::api_entry(api, options));
+            (id, update)
+        };
+        if connected{
+            self.write(UnixMessage::Apis { update }).await?;
+        }
+        Ok(id)
+    }
+
+    /// Remove an api while the node runs, its running requests finish and new ones are answered `NotFound`.
+    /// Refused with `NotAllowed` like `register_api` when the hub does not take api updates
+    pub async fn unregister_api(&self, name: &str) -> Result<(), UnicomError>{
+        let connected = self.api_updates().await?;
+        let id = {
+            let mut api = self.api.write().unwrap();
+            let id = api.iter().find(|(_, entry)| entry.handler.name() == name).map(|(id, _)| *id)
+                .ok_or_else(|| UnicomError::new(UnicomErrorKind::NotFound, &format!("api {} not registered", name)))?;
+            api.remove(&id);
+            id
+        };
+        if connected{
+            self.write(UnixMessage::Apis { update: ApiUpdate::Removed { id } }).await?;
+        }
+        Ok(())
+    }
+
+    /// Run `middleware` around the handlers of every api, in the order they are added
+    pub fn add_middleware(&mut self, middleware: Arc<dyn UnicomMiddleware>){
+        self.middleware.push(middleware);
+    }
+
+    pub fn address(&self) -> &Address{
+        &self.address
+    }
+
+    /// Restrict the capabilities offered to the hub during the init handshake
+    pub fn set_capabilities(&mut self, capabilities: Capabilities){
+        self.capabilities = capabilities;
+    }
+
+    /// Replace the connection limits and reconnect policy, to be called before `run`
+    pub fn set_connection_config(&mut self, connection: ConnectionConfig){
+        self.connection = connection;
+    }
+
+    /// Called with the reason each time the connection to the hub ends, in-flight requests have already failed
+    pub fn on_disconnected<F: Fn(&UnicomError) + Send + Sync + 'static>(&mut self, hook: F){
+        self.disconnected = Some(Box::new(hook));
+    }
+
+    /// Called with the new handshake once the reconnect policy got the node registered again
+    pub fn on_reconnected<F: Fn(&Handshake) + Send + Sync + 'static>(&mut self, hook: F){
+        self.reconnected = Some(Box::new(hook));
+    }
+
+    /// Called with the request and the panic message when a handler panics, the hub is answered an `Internal` error
+    pub fn on_panic<F: Fn(&UnicomRequest, &str) + Send + Sync + 'static>(&mut self, hook: F){
+        self.panicked = Some(Box::new(hook));
+    }
+
+    /// Version and capabilities negotiated with the hub
+    pub async fn handshake(&self) -> Handshake{
+        *self.handshake.lock().await
     }
 
     fn gen_config(&self) -> NodeConfig{
-        let mut config: NodeConfig = self.manifest.clone().try_into().expect("Failed to generate config from manifest");
-        for (id, api) in &self.api{
-            config.add_api((*id).into(), &api.name(), api.description());
+        let mut config = self.node.clone();
+        for (id, entry) in self.api.read().unwrap().iter(){
+            config.add_api(*id, &entry.handler.name(), entry.handler.description());
         }
         config
     }
 
-    async fn connect(&self) -> OwnedReadHalf{
-        let (reader, mut writer) = UnixStream::connect(&self.stream_path).await.unwrap().into_split();
-        write_init(&mut writer, &self.gen_config()).await.expect("write init error");
-        let mut data = self.writer.lock().await;
-        *data = Some(writer);
-        reader
+    async fn connect(&self) -> Result<UnixReader<BoxReader>, UnicomError>{
+        self.register(arch::connect(&self.address).await?).await
     }
 
-    pub async fn request(&self, node: &str, name: &str, parameters: Map<String, Value>) -> Result<Vec<u8>, UnicomError>{
+    /// Send the init frame on an open connection and start its writer once the hub answers
+    async fn register(&self, connection: Connection) -> Result<UnixReader<BoxReader>, UnicomError>{
+        let mut reader = UnixReader::with_max_frame_size(connection.reader, self.connection.max_frame_size);
+        let mut writer = UnixWriter::new(connection.writer);
+        let mut offer = Handshake::new(self.capabilities);
+        if connection.files.is_none(){
+            offer.capabilities.remove(Capabilities::FILE_DESCRIPTORS);
+        }
+        *self.files.lock().await = connection.files;
+        write_init(&mut writer, &self.gen_config(), &offer).await?;
+        let handshake = match self.connection.handshake_timeout{
+            0 => read_handshake(&mut reader, &offer).await?,
+            timeout => tokio::time::timeout(Duration::from_millis(timeout), read_handshake(&mut reader, &offer)).await
+                .map_err(|_| UnicomError::new(UnicomErrorKind::Timeout, &format!("hub did not answer the init frame after {} ms", timeout)))??,
+        };
+        *self.handshake.lock().await = handshake;
+        let outgoing = self.files.lock().await.as_ref().map(|files| files.outgoing.clone());
+        let task = WriterTask::spawn(writer, outgoing, self.connection.max_pending_writes, self.connection.prioritize_control);
+        *self.writer.lock().await = Some(task);
+        Ok(reader)
+    }
+
+    /// Drop the connection state, requests and handlers of this connection can not be answered anymore
+    async fn disconnect(&self, error: &UnicomError){
+        *self.writer.lock().await = None;
+        *self.files.lock().await = None;
+        *self.handshake.lock().await = Handshake::new(Capabilities::empty());
+        self.last_pong.store(0, Ordering::Relaxed);
+        for (_, (handle, cancellation)) in self.running.lock().await.drain(){
+            cancellation.cancel();
+            handle.abort();
+        }
+        self.idle.notify_waiters();
+        self.incoming.lock().await.clear();
+        self.incoming_files.lock().await.clear();
+        // bodies waiting for credit fail instead of waiting forever
+        for (_, credit) in self.windows.lock().unwrap().drain(){
+            credit.close();
+        }
+        self.pending.fail_all(UnicomError::new(UnicomErrorKind::LostConnection, &error.description)).await;
+        if let Some(hook) = &self.disconnected{
+            hook(error);
+        }
+    }
+
+    /// Connect and register again following the reconnect policy, `None` once it gives up
+    async fn reconnect(&self) -> Option<UnixReader<BoxReader>>{
+        let policy = self.connection.reconnect.clone()?;
+        let mut attempt = 0;
+        loop{
+            if self.closing.load(Ordering::Relaxed){
+                return None
+            }
+            if policy.max_attempts != 0 && attempt >= policy.max_attempts{
+                println!("error reconnect gave up after {} attempts", attempt);
+                return None
+            }
+            tokio::time::sleep(policy.delay(attempt)).await;
+            attempt += 1;
+            match self.connect().await{
+                Ok(reader) => {
+                    if let Some(hook) = &self.reconnected{
+                        hook(&self.handshake().await);
+                    }
+                    return Some(reader)
+                },
+                Err(e) => println!("error reconnect {:?}", e),
+            }
+        }
+    }
+
+    fn new_request(node: &str, name: &str, parameters: Map<String, Value>) -> UnicomRequest{
         let mut data = UnicomRequest::new();
         data.node_name = node.to_string();
         data.name = name.to_string();
         data.parameters = parameters;
+        data
+    }
+
+    async fn write(&self, message: UnixMessage) -> Result<(), UnicomError>{
+        self.send(message, Vec::new()).await
+    }
+
+    /// Queue a frame for the writer task, `files` leave on the transport with this frame
+    async fn send(&self, message: UnixMessage, files: Vec<File>) -> Result<(), UnicomError>{
+        self.handshake.lock().await.check(&message)?;
+        if !files.is_empty() && self.files.lock().await.is_none(){
+            return Err(UnicomError::new(UnicomErrorKind::NotAllowed, "transport can not pass files"))
+        }
+        let sender = match self.writer.lock().await.as_ref(){
+            Some(writer) => writer.sender(),
+            None => return Err(UnicomError::new(UnicomErrorKind::LostConnection, "not connected")),
+        };
+        // a slow hub makes senders wait here once the writer queue is full
+        sender.send(message, files.into_iter().map(OwnedFd::from).collect()).await
+    }
+
+    /// Pass files by descriptor ahead of the request or response with the same id
+    async fn write_files(&self, id: u64, origin: ChunkOrigin, files: Vec<File>) -> Result<(), UnicomError>{
+        if files.is_empty(){
+            return Ok(())
+        }
+        if files.len() > MAX_FILES{
+            return Err(UnicomError::new(UnicomErrorKind::ParameterInvalid, &format!("at most {} files per message", MAX_FILES)))
+        }
+        self.send(UnixMessage::Files { id, origin, count: files.len() }, files).await
+    }
+
+    /// Send the status, headers and content type ahead of the response with the same id, a hub that can
+    /// not read them only gets the data
+    async fn write_head(&self, id: u64, head: ResponseHead) -> Result<(), UnicomError>{
+        if head.is_empty() || !self.handshake().await.capabilities.contains(Capabilities::RESPONSE_HEAD){
+            return Ok(())
+        }
+        self.write(UnixMessage::Head { id, head }).await
+    }
+
+    async fn take_received_files(&self, count: usize) -> Result<Vec<File>, UnicomError>{
+        let channel = self.files.lock().await.clone()
+            .ok_or_else(|| UnicomError::new(UnicomErrorKind::DataInvalid, "files frame on a transport without files"))?;
+        let mut received = channel.received.lock().unwrap();
+        if received.len() < count{
+            received.clear();
+            return Err(UnicomError::new(UnicomErrorKind::DataInvalid, "files frame without its descriptors"))
+        }
+        Ok(received.drain(..count).map(File::from).collect())
+    }
+
+    /// Close the descriptors no files frame claimed. Descriptors arrive with the first bytes of their
+    /// files frame, once every byte received is decoded the ones left were never announced
+    fn drop_unannounced_files(files: &FdChannel){
+        let mut received = files.received.lock().unwrap();
+        if !received.is_empty(){
+            println!("error receive files: {} descriptors without files frame closed", received.len());
+            received.clear();
+        }
+    }
+
+    /// Timeout of the connection config applied to requests without their own
+    fn default_timeout(&self) -> Option<Duration>{
+        match self.connection.request_timeout{
+            0 => None,
+            timeout => Some(Duration::from_millis(timeout)),
+        }
+    }
+
+    /// Wait for the answer to a pending request, on timeout the guard drops armed so the
+    /// pending entry is removed and the callee is told to stop
+    async fn wait(&self, id: u64, notify: &Notify, mut guard: PendingGuard, timeout: Option<Duration>) -> Result<(), UnicomError>{
+        match timeout{
+            Some(timeout) => {
+                if tokio::time::timeout(timeout, notify.notified()).await.is_err(){
+                    return Err(UnicomError::new(UnicomErrorKind::Timeout, &format!("request {} not answered after {:?}", id, timeout)))
+                }
+            },
+            None => notify.notified().await,
+        }
+        guard.disarm();
+        Ok(())
+    }
+
+    pub async fn request(&self, node: &str, name: &str, parameters: Map<String, Value>) -> Result<Vec<u8>, UnicomError>{
+        self.request_with_timeout(node, name, parameters, self.default_timeout()).await
+    }
+
+    /// Same as `request` waiting at most `timeout` instead of the configured one, `None` waits forever.
+    /// The callee reads the time it has left with `UnicomRequest::remaining`
+    pub async fn request_with_timeout(&self, node: &str, name: &str, parameters: Map<String, Value>, timeout: Option<Duration>) -> Result<Vec<u8>, UnicomError>{
+        let mut data = ServerConnection::new_request(node, name, parameters);
+        data.timeout = timeout.map(|timeout| timeout.as_millis() as u64);
         let (id, notify) = self.pending.create().await;
+        let guard = PendingGuard::new(id, self.cancel.clone());
 
-        write_message(&mut self.writer.lock().await.as_mut().unwrap(), UnixMessage::Request { id, data }).await?;
+        self.write(UnixMessage::Request { id, data }).await?;
+        self.wait(id, &notify, guard, timeout).await?;
 
-        notify.notified().await;
+        self.pending.get(id).await
+    }
+
+    /// Typed `request`: `parameters` must serialize to a json object, the answer is decoded from json
+    pub async fn request_json<P: Serialize, R: DeserializeOwned>(&self, node: &str, name: &str, parameters: &P) -> Result<R, UnicomError>{
+        let parameters = match serde_json::to_value(parameters){
+            Ok(Value::Object(parameters)) => parameters,
+            Ok(value) => return Err(UnicomError::new(UnicomErrorKind::ParameterInvalid, &format!("parameters for {}/{} are not an object {}", node, name, value))),
+            Err(e) => return Err(UnicomError::new(UnicomErrorKind::ParameterInvalid, &format!("parameters for {}/{} error {}", node, name, e))),
+        };
+        let data = self.request(node, name, parameters).await?;
+        serde_json::from_slice(&data)
+            .map_err(|e| UnicomError::new(UnicomErrorKind::DataInvalid, &format!("answer of {}/{} error {}", node, name, e)))
+    }
+
+    /// Same as `request` with the status, headers and content type set by the answering node
+    pub async fn request_response(&self, node: &str, name: &str, parameters: Map<String, Value>) -> Result<UnicomResponse, UnicomError>{
+        let timeout = self.default_timeout();
+        let mut data = ServerConnection::new_request(node, name, parameters);
+        data.timeout = timeout.map(|timeout| timeout.as_millis() as u64);
+        let (id, notify) = self.pending.create().await;
+        let guard = PendingGuard::new(id, self.cancel.clone());
+
+        self.write(UnixMessage::Request { id, data }).await?;
+        self.wait(id, &notify, guard, timeout).await?;
+
+        Ok(self.pending.get_response(id).await?.0)
+    }
+
+    /// Same as `request` but the response body is received chunk by chunk, returns once the answer starts
+    /// with the status, headers and content type set by the answering node. The configured timeout bounds
+    /// the whole stream
+    pub async fn request_stream(&self, node: &str, name: &str, parameters: Map<String, Value>) -> Result<(ResponseHead, UnicomStream), UnicomError>{
+        self.request_stream_with_timeout(node, name, parameters, self.default_timeout()).await
+    }
+
+    /// Same as `request_stream` with its own timeout, `None` waits forever. A stream not ended in
+    /// time yields a `Timeout` error and the request is cancelled
+    pub async fn request_stream_with_timeout(&self, node: &str, name: &str, parameters: Map<String, Value>, timeout: Option<Duration>) -> Result<(ResponseHead, UnicomStream), UnicomError>{
+        let mut data = ServerConnection::new_request(node, name, parameters);
+        data.timeout = timeout.map(|timeout| timeout.as_millis() as u64);
+        let (id, head, mut stream) = self.pending.create_stream().await;
+        stream.set_guard(PendingGuard::new(id, self.cancel.clone()));
+        if self.flow_control().await{
+            stream.set_window(id, ChunkOrigin::Response, self.window.clone());
+        }
+        if let Some(timeout) = timeout{
+            stream.set_timeout(timeout);
+        }
+
+        self.write(UnixMessage::Request { id, data }).await?;
+
+        // dropping the stream on timeout cancels the request
+        let head = match timeout{
+            Some(timeout) => tokio::time::timeout(timeout, head).await
+                .map_err(|_| UnicomError::new(UnicomErrorKind::Timeout, &format!("request {} not answered after {:?}", id, timeout)))?,
+            None => head.await,
+        };
+        match head{
+            Ok(Ok(head)) => Ok((head, stream)),
+            Ok(Err(error)) => Err(error),
+            Err(_) => Err(UnicomError::new(UnicomErrorKind::LostConnection, &format!("request {} ended without an answer", id))),
+        }
+    }
+
+    /// Same as `request` with a body streamed after the request, read on the other side with `UnicomRequest::take_body`
+    pub async fn request_with_body(&self, node: &str, name: &str, parameters: Map<String, Value>, body: BodyStream) -> Result<Vec<u8>, UnicomError>{
+        let timeout = self.default_timeout();
+        let mut data = ServerConnection::new_request(node, name, parameters);
+        data.streamed = true;
+        data.timeout = timeout.map(|timeout| timeout.as_millis() as u64);
+        let (id, notify) = self.pending.create().await;
+        let guard = PendingGuard::new(id, self.cancel.clone());
+
+        self.write(UnixMessage::Request { id, data }).await?;
+        // an answer arriving before the whole body is sent stops sending it, the timeout bounds both
+        let sending = self.write_stream(id, ChunkOrigin::Request, body);
+        let waiting = self.wait(id, &notify, guard, timeout);
+        tokio::pin!(sending, waiting);
+        tokio::select!{
+            sent = &mut sending => {
+                sent?;
+                waiting.await?;
+            },
+            waited = &mut waiting => waited?,
+        }
 
         self.pending.get(id).await
     }
 
-    pub async fn run(server: &Arc<ServerConnection>) -> Arc<Notify>{
-        let mut reader = server.connect().await;
-        let notify = Arc::new(Notify::new());
-        let notify_back = notify.clone();
+    /// Same as `request` with files passed by descriptor, the files sent back with the response are returned
+    pub async fn request_with_files(&self, node: &str, name: &str, parameters: Map<String, Value>, files: Vec<File>) -> Result<(Vec<u8>, Vec<File>), UnicomError>{
+        let timeout = self.default_timeout();
+        let mut data = ServerConnection::new_request(node, name, parameters);
+        data.timeout = timeout.map(|timeout| timeout.as_millis() as u64);
+        let (id, notify) = self.pending.create().await;
+        let guard = PendingGuard::new(id, self.cancel.clone());
+
+        self.write_files(id, ChunkOrigin::Request, files).await?;
+        self.write(UnixMessage::Request { id, data }).await?;
+        self.wait(id, &notify, guard, timeout).await?;
+
+        self.pending.get_with_files(id).await
+    }
+
+    /// Send a body as chunk frames followed by the end marker, other frames can be queued between chunks.
+    /// With flow control each chunk waits for credit from the receiver
+    pub async fn write_stream(&self, id: u64, origin: ChunkOrigin, mut body: BodyStream) -> Result<(), UnicomError>{
+        if !self.handshake().await.capabilities.contains(Capabilities::STREAMING){
+            // the hub can not read chunks, a response is sent in one frame instead
+            if origin == ChunkOrigin::Request{
+                return Err(UnicomError::new(UnicomErrorKind::NotAllowed, "streaming not negotiated with the hub"))
+            }
+            let mut data = Vec::new();
+            while let Some(chunk) = body.next().await{
+                match chunk{
+                    Ok(chunk) => data.extend_from_slice(&chunk),
+                    Err(error) => {
+                        self.write(UnixMessage::Error { id, error: error.clone() }).await?;
+                        return Err(error)
+                    },
+                }
+            }
+            return self.write(UnixMessage::Response { id, data }).await
+        }
+        let credit = match self.flow_control().await{
+            true => Some(Credit::open(&self.windows, id, origin)),
+            false => None,
+        };
+        while let Some(chunk) = body.next().await{
+            let chunk = match chunk{
+                Ok(chunk) => chunk,
+                Err(error) => {
+                    if origin == ChunkOrigin::Response{
+                        self.write(UnixMessage::Error { id, error: error.clone() }).await?;
+                    }
+                    else{
+                        self.write(UnixMessage::Chunk { id, origin, data: Vec::new() }).await?;
+                    }
+                    return Err(error)
+                },
+            };
+            for data in chunk.chunks(CHUNK_SIZE){
+                if let Some(credit) = &credit{
+                    credit.take().await?;
+                }
+                self.write(UnixMessage::Chunk { id, origin, data: data.to_vec() }).await?;
+            }
+        }
+        self.write(UnixMessage::Chunk { id, origin, data: Vec::new() }).await
+    }
+
+    async fn flow_control(&self) -> bool{
+        self.handshake().await.capabilities.contains(Capabilities::FLOW_CONTROL)
+    }
+
+    /// Credit granted by the hub for a body this node sends, unknown bodies already ended
+    fn grant(&self, id: u64, origin: ChunkOrigin, credit: u32){
+        if let Some(permits) = self.windows.lock().unwrap().get(&(id, origin)){
+            permits.add_permits(credit as usize);
+        }
+    }
+
+    /// Tell the hub how many more chunks of each body this node read
+    async fn send_windows(server: Arc<ServerConnection>, mut granted: mpsc::UnboundedReceiver<(u64, ChunkOrigin, u32)>){
+        while let Some((id, origin, credit)) = granted.recv().await{
+            if let Err(e) = server.write(UnixMessage::Window { id, origin, credit }).await{
+                println!("error send window {:?}", e);
+            }
+        }
+    }
+
+    /// Forward a chunk to the handler reading the request body. Without flow control a handler too far
+    /// behind gets its body failed and the rest of it is dropped
+    async fn push_request_chunk(&self, id: u64, data: Vec<u8>) -> Result<(), UnicomError>{
+        let mut incoming = self.incoming.lock().await;
+        if data.is_empty(){
+            incoming.remove(&id);
+            return Ok(())
+        }
+        match incoming.get(&id).cloned(){
+            Some(sender) => {
+                let pushed = push_body(sender, Bytes::from(data));
+                if pushed.is_err(){
+                    incoming.remove(&id);
+                }
+                pushed
+            },
+            None => Ok(()),
+        }
+    }
+
+    /// Tell the hub to stop answering a request of this node
+    async fn send_cancel(&self, id: u64){
+        if self.handshake().await.capabilities.contains(Capabilities::CANCELLATION){
+            if let Err(e) = self.write(UnixMessage::Cancel { id }).await{
+                println!("error send cancel {:?}", e);
+            }
+        }
+    }
+
+    /// Tell the hub about requests dropped by their caller and forget them
+    async fn send_cancels(server: Arc<ServerConnection>, mut cancelled: mpsc::UnboundedReceiver<u64>){
+        while let Some(id) = cancelled.recv().await{
+            if server.pending.remove(id).await{
+                server.send_cancel(id).await;
+            }
+        }
+    }
+
+    /// Abort the handler of a request cancelled by its caller
+    async fn cancel_request(&self, id: u64){
+        let mut running = self.running.lock().await;
+        if let Some((handle, cancellation)) = running.remove(&id){
+            cancellation.cancel();
+            handle.abort();
+            // an aborted handler never gets to remove itself
+            if running.is_empty(){
+                self.idle.notify_waiters();
+            }
+        }
+        drop(running);
+        self.incoming.lock().await.remove(&id);
+    }
+
+    async fn handle_request(server: Arc<ServerConnection>, id: u64, data: UnicomRequest, context: RequestContext, entry: Option<Arc<ApiEntry>>, admission: Option<Admission>){
+        let permit = match admission{
+            Some(admission) => Some(admission.run().await),
+            None => None,
+        };
+        ServerConnection::dispatch(&server, id, data, context, entry).await;
+        drop(permit);
+        let mut running = server.running.lock().await;
+        running.remove(&id);
+        if running.is_empty(){
+            server.idle.notify_waiters();
+        }
+    }
+
+    /// Stop the node: new requests from the hub are refused, running handlers get up to `grace` to answer,
+    /// then the requests still waiting for an answer fail and the hub is sent `Quit` before the socket closes
+    pub async fn shutdown(&self, grace: Duration){
+        self.closing.store(true, Ordering::Relaxed);
+        let deadline = tokio::time::Instant::now() + grace;
+        loop{
+            let idle = self.idle.notified();
+            tokio::pin!(idle);
+            idle.as_mut().enable();
+            if self.running.lock().await.is_empty(){
+                break
+            }
+            if tokio::time::timeout_at(deadline, idle).await.is_err(){
+                println!("error shutdown {} handlers still running", self.running.lock().await.len());
+                break
+            }
+        }
+        self.pending.fail_all(UnicomError::new(UnicomErrorKind::LostConnection, "node shutting down")).await;
+        match tokio::time::timeout_at(deadline, self.write(UnixMessage::Quit)).await{
+            Ok(Ok(())) => (),
+            Ok(Err(e)) => println!("error send quit {:?}", e),
+            Err(_) => println!("error send quit writer queue still full after {:?}", grace),
+        }
+        let writer = self.writer.lock().await.take();
+        if let Some(writer) = writer{
+            // the frames still queued get the rest of the grace period, a stuck hub does not hold the node
+            writer.close(deadline.saturating_duration_since(tokio::time::Instant::now())).await;
+        }
+        self.stop.notify_one();
+    }
+
+    /// Shutdown with `grace` on the first SIGTERM or SIGINT
+    pub fn shutdown_on_signal(server: &Arc<ServerConnection>, grace: Duration) -> Result<(), UnicomError>{
+        let mut terminate = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())?;
+        let mut interrupt = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::interrupt())?;
         let server = server.clone();
         tokio::spawn(async move {
-            loop {
-                let mess = match read_message(&mut reader).await {
-                    Ok(mess) => mess,
-                    Err(e) => {
-                        println!("error read message {:?}",e);
-                        notify_back.notify_one();
-                        return
+            tokio::select!{
+                _ = terminate.recv() => (),
+                _ = interrupt.recv() => (),
+            }
+            server.shutdown(grace).await;
+        });
+        Ok(())
+    }
+
+    /// Send the answer of request `id`, a connection lost meanwhile is only logged
+    async fn answer(&self, message: UnixMessage){
+        if let Err(e) = self.write(message).await{
+            println!("error write answer {:?}", e);
+        }
+    }
+
+    /// Answer from the read loop without waiting for room in the writer queue, a hub slow to read
+    /// would otherwise stop the node from reading what the hub sends
+    fn reply(server: &Arc<ServerConnection>, message: UnixMessage){
+        let server = server.clone();
+        tokio::spawn(async move {
+            server.answer(message).await;
+        });
+    }
+
+    /// Report a panic of the handler of `request` and turn it into the error sent to the hub
+    fn panic_error(&self, request: &UnicomRequest, payload: Box<dyn Any + Send>) -> UnicomError{
+        let message = match payload.downcast::<String>(){
+            Ok(message) => *message,
+            Err(payload) => match payload.downcast::<&'static str>(){
+                Ok(message) => message.to_string(),
+                Err(_) => "unknown panic".to_string(),
+            },
+        };
+        if let Some(hook) = &self.panicked{
+            hook(request, &message);
+        }
+        UnicomError::new(UnicomErrorKind::Internal, &format!("api {} panicked: {}", request.name, message))
+    }
+
+    async fn dispatch(server: &Arc<ServerConnection>, id: u64, request: UnicomRequest, mut context: RequestContext, entry: Option<Arc<ApiEntry>>){
+        let data = &request;
+        let entry = match entry{
+            Some(entry) => entry,
+            None => {
+                let error = UnicomError::new(UnicomErrorKind::NotFound, &format!("api id not found {:?}", data));
+                server.answer(UnixMessage::Error { id, error }).await;
+                return
+            },
+        };
+
+        let middleware: Vec<_> = server.middleware.iter().chain(entry.middleware.iter()).cloned().collect();
+        let stream = std::sync::Mutex::new(None);
+        // a panicking handler still gets the hub an answer, otherwise its caller waits forever
+        let ret = match AssertUnwindSafe(Next::new(&middleware, &entry.handler, &stream).run(server, data, &mut context)).catch_unwind().await{
+            Ok(ret) => ret,
+            Err(payload) => Err(server.panic_error(data, payload)),
+        };
+        let stream = stream.into_inner().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        match (ret, stream){
+            (Ok(_), Some(body)) => {
+                if let Err(error) = server.write_head(id, request.take_response_head()).await{
+                    server.answer(UnixMessage::Error { id, error }).await;
+                    return
+                }
+                match AssertUnwindSafe(server.write_stream(id, ChunkOrigin::Response, body)).catch_unwind().await{
+                    Ok(Ok(())) => (),
+                    Ok(Err(e)) => println!("error stream response {:?}", e),
+                    Err(payload) => {
+                        let error = server.panic_error(data, payload);
+                        server.answer(UnixMessage::Error { id, error }).await;
                     },
+                }
+            },
+            (Ok(data), None) => {
+                let sent = match server.write_files(id, ChunkOrigin::Response, request.take_response_files()).await{
+                    Ok(()) => server.write_head(id, request.take_response_head()).await,
+                    Err(error) => Err(error),
                 };
-                let notify_back = notify_back.clone();
-                let server = server.clone();
-                tokio::spawn(async move {
-                    match mess {
-                        UnixMessage::Error { id, error } => {
-                            if id == 0{
-                                println!("config error : {:?}", error);
-                                notify_back.notify_one();
-                                return
-                            }
-                            server.pending.update(id, Err(error)).await.unwrap()
+                if let Err(error) = sent{
+                    server.answer(UnixMessage::Error { id, error }).await;
+                    return
+                }
+                server.answer(UnixMessage::Response { id, data }).await;
+
+            },
+            (Err(error), _) => {
+                server.answer(UnixMessage::Error { id, error }).await;
+            },
+        }
+    }
+
+    async fn read_loop(server: &Arc<ServerConnection>, mut reader: UnixReader<BoxReader>) -> Disconnect{
+        let files = server.files.lock().await.clone();
+        loop {
+            if let Some(files) = files.as_ref().filter(|_| reader.buffered() == 0){
+                ServerConnection::drop_unannounced_files(files);
+            }
+            // frames that can not be decoded come back as rejected or invalid, an error here means the stream is broken
+            let mess = match read_message(&mut reader).await {
+                Ok(mess) => mess,
+                Err(e) => {
+                    println!("error read message {:?}",e);
+                    return Disconnect::Lost(e)
+                },
+            };
+            if let Err(e) = server.handshake().await.check(&mess){
+                println!("error handle message {:?}", e);
+                continue
+            }
+            // responses and chunks are handled in order, requests get their own task
+            let ret = match mess {
+                UnixMessage::Error { id, error } => {
+                    if id == 0{
+                        println!("config error : {:?}", error);
+                        return Disconnect::Closed(error)
+                    }
+                    server.pending.update(id, Err(error)).await
+                },
+                UnixMessage::Response { id, data } => server.pending.update(id, Ok(data)).await,
+                UnixMessage::Chunk { id, origin: ChunkOrigin::Response, data } => {
+                    let pushed = server.pending.push_chunk(id, data).await;
+                    if pushed.is_err(){
+                        let server = server.clone();
+                        tokio::spawn(async move {
+                            server.send_cancel(id).await;
+                        });
+                    }
+                    pushed
+                },
+                UnixMessage::Chunk { id, origin: ChunkOrigin::Request, data } => server.push_request_chunk(id, data).await,
+                UnixMessage::Request { id, .. } if server.closing.load(Ordering::Relaxed) => {
+                    server.incoming_files.lock().await.remove(&id);
+                    ServerConnection::reply(server, UnixMessage::Error { id, error: UnicomError::new(UnicomErrorKind::LostConnection, "node shutting down") });
+                    Ok(())
+                },
+                UnixMessage::Request { id, data } => {
+                    let files = server.incoming_files.lock().await.remove(&id);
+                    // a full api is refused here so a burst of requests does not start a handler each
+                    // the api is looked up once so a request accepted before the api is removed still runs
+                    let entry = server.find_api(data.id);
+                    let admission = match entry.as_ref().and_then(|entry| entry.limit.as_ref()){
+                        Some(limit) => match limit.admit(&data.name){
+                            Ok(admission) => Some(admission),
+                            Err(error) => {
+                                ServerConnection::reply(server, UnixMessage::Error { id, error });
+                                continue
+                            },
                         },
-                        UnixMessage::Response { id, data } => server.pending.update(id, Ok(data)).await.unwrap(),
-                        UnixMessage::Request { id, data } => {
-                            let handler = match server.api.get(&(data.id as u16)){
-                                Some(handler) => handler,
-                                None => {
-                                    let error = UnicomError::new(UnicomErrorKind::NotFound, &format!("api id not found {:?}", data));
-                                    write_message(&mut server.writer.lock().await.as_mut().unwrap(), 
-                                    UnixMessage::Error { id, error: error.into() }).await.unwrap();
-                                    return
-                                },
-                            };
-                            let ret = match data.method{
-                                MethodKind::GET => handler.api_get(&server, &data),
-                                MethodKind::PUT => handler.api_put(&server, &data),
-                                MethodKind::POST => handler.api_post(&server, &data),
-                                MethodKind::DELETE => handler.api_delete(&server, &data),
-                            }.await;
-            
-                            match ret{
-                                Ok(data) => {
-                                    write_message(&mut server.writer.lock().await.as_mut().unwrap(), 
-                                UnixMessage::Response { id, data }).await.unwrap();
-            
-                                },
-                                Err(error) => {
-                                    write_message(&mut server.writer.lock().await.as_mut().unwrap(), 
-                                    UnixMessage::Error { id, error: error.into() }).await.unwrap();
-                                },
-                            }
-                            
+                        None => None,
+                    };
+                    if let Some(files) = files{
+                        data.set_files(files);
+                    }
+                    if data.streamed{
+                        let (sender, mut body) = UnicomStream::channel();
+                        if server.flow_control().await{
+                            body.set_window(id, ChunkOrigin::Request, server.window.clone());
+                        }
+                        server.incoming.lock().await.insert(id, sender);
+                        data.set_body(body);
+                    }
+                    // the handler removes itself once done, which waits for this insert
+                    let context = RequestContext::new(id, &data);
+                    let cancellation = context.cancellation.clone();
+                    let mut running = server.running.lock().await;
+                    let handle = tokio::spawn(ServerConnection::handle_request(server.clone(), id, data, context, entry, admission));
+                    running.insert(id, (handle.abort_handle(), cancellation));
+                    Ok(())
+                },
+                UnixMessage::Quit => return Disconnect::Closed(UnicomError::new(UnicomErrorKind::LostConnection, "hub quit")),
+                UnixMessage::Cancel { id } => {
+                    server.cancel_request(id).await;
+                    Ok(())
+                },
+                UnixMessage::Files { id, origin, count } => {
+                    match server.take_received_files(count).await{
+                        Ok(files) if origin == ChunkOrigin::Request => {
+                            server.incoming_files.lock().await.insert(id, files);
+                            Ok(())
                         },
-                        UnixMessage::Quit => notify_back.notify_one(),
+                        Ok(files) => server.pending.attach_files(id, files).await,
+                        Err(e) => Err(e),
                     }
-                });
-                
+                },
+                UnixMessage::Ping { id } => {
+                    ServerConnection::reply(server, UnixMessage::Pong { id });
+                    Ok(())
+                },
+                UnixMessage::Pong { id } => {
+                    server.last_pong.store(id, Ordering::Relaxed);
+                    Ok(())
+                },
+                UnixMessage::Apis { .. } => Err(UnicomError::new(UnicomErrorKind::DataInvalid, "api update sent by the hub")),
+                UnixMessage::Head { id, head } => server.pending.attach_head(id, head).await,
+                UnixMessage::Window { id, origin, credit } => {
+                    server.grant(id, origin, credit);
+                    Ok(())
+                },
+                UnixMessage::Rejected { id, origin: ChunkOrigin::Request, error } => {
+                    if let Some(sender) = server.incoming.lock().await.remove(&id){
+                        end_body(sender, Err(error.clone()));
+                    }
+                    server.incoming_files.lock().await.remove(&id);
+                    ServerConnection::reply(server, UnixMessage::Error { id, error });
+                    Ok(())
+                },
+                UnixMessage::Rejected { id, origin: ChunkOrigin::Response, error } => server.pending.update(id, Err(error)).await,
+                UnixMessage::Invalid { error, .. } => Err(error),
+            };
+            if let Err(e) = ret{
+                println!("error handle message {:?}", e);
+            }
+        }
+    }
+
+    /// Ping the hub, returns once it missed too many pongs and is considered dead
+    async fn heartbeat(server: &Arc<ServerConnection>) -> UnicomError{
+        let period = server.connection.heartbeat_interval;
+        if period == 0 || server.connection.heartbeat_misses == 0 || !server.handshake().await.capabilities.contains(Capabilities::HEARTBEAT){
+            return futures::future::pending().await
+        }
+        let mut interval = tokio::time::interval(Duration::from_millis(period));
+        interval.tick().await;
+        let mut ping = 0;
+        let mut missed = 0;
+        loop{
+            interval.tick().await;
+            if ping != 0 && server.last_pong.load(Ordering::Relaxed) != ping{
+                missed += 1;
+            }
+            else{
+                missed = 0;
+            }
+            if missed >= server.connection.heartbeat_misses{
+                println!("error heartbeat {} pings missed", missed);
+                return UnicomError::new(UnicomErrorKind::LostConnection, "hub stopped answering pings")
+            }
+            ping += 1;
+            if let Err(e) = server.write(UnixMessage::Ping { id: ping }).await{
+                println!("error send ping {:?}", e);
+            }
+        }
+    }
+
+    /// Connect to the hub and serve its frames, the notify fires once the node is disconnected for good
+    pub async fn run(server: &Arc<ServerConnection>) -> Arc<Notify>{
+        let connected = server.connect().await;
+        ServerConnection::serve(server, connected).await
+    }
+
+    /// Same as `run` on a connection opened by the caller, like one end of a `tokio::io::duplex` pair.
+    /// Reconnecting still goes through the address
+    pub async fn run_on(server: &Arc<ServerConnection>, connection: Connection) -> Arc<Notify>{
+        let connected = server.register(connection).await;
+        ServerConnection::serve(server, connected).await
+    }
+
+    async fn serve(server: &Arc<ServerConnection>, connected: Result<UnixReader<BoxReader>, UnicomError>) -> Arc<Notify>{
+        let notify = Arc::new(Notify::new());
+        let reader = match connected{
+            Ok(reader) => reader,
+            Err(e) => {
+                println!("config error : {:?}", e);
+                notify.notify_one();
+                return notify
+            },
+        };
+        if let Some(cancelled) = server.cancelled.lock().await.take(){
+            tokio::spawn(ServerConnection::send_cancels(server.clone(), cancelled));
+        }
+        if let Some(granted) = server.granted.lock().await.take(){
+            tokio::spawn(ServerConnection::send_windows(server.clone(), granted));
+        }
+        let server = server.clone();
+        let notify_back = notify.clone();
+        tokio::spawn(async move {
+            let mut reader = reader;
+            loop{
+                let end = tokio::select!{
+                    end = ServerConnection::read_loop(&server, reader) => end,
+                    error = ServerConnection::heartbeat(&server) => Disconnect::Lost(error),
+                    _ = server.stop.notified() => Disconnect::Closed(UnicomError::new(UnicomErrorKind::LostConnection, "node shut down")),
+                };
+                match end{
+                    Disconnect::Closed(error) => {
+                        server.disconnect(&error).await;
+                        break
+                    },
+                    Disconnect::Lost(error) => {
+                        server.disconnect(&error).await;
+                        match server.reconnect().await{
+                            Some(next) => reader = next,
+                            None => break,
+                        }
+                    },
+                }
             }
+            notify_back.notify_one();
         });
 
         notify
@@ -0,0 +1,77 @@
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+
+use crate::context::RequestContext;
+use crate::error::UnicomError;
+use crate::node::{api::MethodKind, message::{request::UnicomRequest, stream::BodyStream}};
+use crate::{ServerConnection, UnicomApi};
+
+/// Runs around the handlers of incoming requests, for logging, timing, auth checks or error translation.
+///
+/// A middleware calls `next.run` to go on with the chain and the handler, or returns without calling it
+/// to answer in their place. The answer of a streamed response (`UnicomApi::api_stream`) is seen empty.
+/// Values put in `context.extensions` are seen by the middleware after it and the handler.
+///
+/// ```no_run
+/// use std::{sync::Arc, time::Instant};
+/// use async_trait::async_trait;
+/// use unicom_lib::{ServerConnection, context::RequestContext, error::UnicomError, middleware::{Next, UnicomMiddleware}, node::message::request::UnicomRequest};
+///
+/// struct Timing;
+///
+/// #[async_trait]
+/// impl UnicomMiddleware for Timing{
+///     async fn handle(&self, server: &Arc<ServerConnection>, request: &UnicomRequest, context: &mut RequestContext, next: Next<'_>) -> Result<Vec<u8>, UnicomError>{
+///         let start = Instant::now();
+///         let ret = next.run(server, request, context).await;
+///         println!("{} took {:?}", request.name, start.elapsed());
+///         ret
+///     }
+/// }
+/// ```
+#[async_trait]
+pub trait UnicomMiddleware: Send + Sync{
+    async fn handle(&self, server: &Arc<ServerConnection>, request: &UnicomRequest, context: &mut RequestContext, next: Next<'_>) -> Result<Vec<u8>, UnicomError>;
+}
+
+/// Rest of the middleware chain followed by the api handler
+pub struct Next<'a>{
+    middleware: &'a [Arc<dyn UnicomMiddleware>],
+    handler: &'a Arc<dyn UnicomApi>,
+    stream: &'a Mutex<Option<BodyStream>>,
+}
+
+impl<'a> Next<'a>{
+    pub(crate) fn new(middleware: &'a [Arc<dyn UnicomMiddleware>], handler: &'a Arc<dyn UnicomApi>, stream: &'a Mutex<Option<BodyStream>>) -> Next<'a>{
+        Next { middleware, handler, stream }
+    }
+
+    pub async fn run(self, server: &Arc<ServerConnection>, request: &UnicomRequest, context: &mut RequestContext) -> Result<Vec<u8>, UnicomError>{
+        match self.middleware.split_first(){
+            Some((middleware, rest)) => {
+                middleware.handle(server, request, context, Next { middleware: rest, ..self }).await
+            },
+            None => self.call(server, request, context).await,
+        }
+    }
+
+    async fn call(self, server: &Arc<ServerConnection>, request: &UnicomRequest, context: &RequestContext) -> Result<Vec<u8>, UnicomError>{
+        // HEAD and OPTIONS answer without body, they never stream one
+        if !matches!(request.method, MethodKind::HEAD | MethodKind::OPTIONS){
+            if let Some(body) = self.handler.api_stream(server, request, context).await?{
+                *self.stream.lock().unwrap() = Some(body);
+                return Ok(Vec::new())
+            }
+        }
+        match request.method{
+            MethodKind::GET => self.handler.api_get(server, request, context).await,
+            MethodKind::PUT => self.handler.api_put(server, request, context).await,
+            MethodKind::POST => self.handler.api_post(server, request, context).await,
+            MethodKind::DELETE => self.handler.api_delete(server, request, context).await,
+            MethodKind::PATCH => self.handler.api_patch(server, request, context).await,
+            MethodKind::HEAD => self.handler.api_head(server, request, context).await,
+            MethodKind::OPTIONS => self.handler.api_options(server, request, context).await,
+        }
+    }
+}
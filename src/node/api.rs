@@ -1,5 +1,6 @@
 use crate::error::{UnicomError, UnicomErrorKind};
 use hyper::Method;
+use serde::de::DeserializeOwned;
 use serde_json::{Map, Value};
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -18,16 +19,29 @@ impl Api {
         }
     }
 
+    /// Declaration of `method`, HEAD is answered by GET when it is not declared
     pub fn get_method(&self, method: &MethodKind) -> Result<&ApiMethod, UnicomError>{
         for api_method in &self.methods{
             if api_method.method == *method {
                 return Ok(api_method)
             }
         }
+        if *method == MethodKind::HEAD{
+            return self.get_method(&MethodKind::GET)
+        }
         Err(UnicomError::new(UnicomErrorKind::MethodNotAllowed, &format!("Methode {:?} not allowed", method)))
     }
 }
 
+/// Change to the api list of a connected node, sent to the hub in an apis frame
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub enum ApiUpdate{
+    Added(Api),
+    Removed{
+        id: u64,
+    },
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct ApiMethod{
     pub method: MethodKind,
@@ -40,22 +54,28 @@ impl ApiMethod {
     }
 
     pub fn generate_parameters(&self, parameters: &Map<String, Value>) -> Result<(), UnicomError>{
-        for parameter in &self.parameters{
-            match parameters.get(&parameter.name){
-                Some(value) => {
-                    if !parameter.check(value){
-                        return Err(UnicomError::new(UnicomErrorKind::ParameterInvalid, &format!("Wrong type of parameter {} {:?}", &parameter.name, value)))
-                    }
-                },
-                None => {
-                    if parameter.mandatory{
-                        return Err(UnicomError::new(UnicomErrorKind::ParameterInvalid, &format!("parameter {} is missing", &parameter.name)))
-                    }
-                },
-            }
+        check_parameters(&self.parameters, parameters)
+    }
+}
+
+/// Check the kind of the given parameters and that the mandatory ones are there, a null parameter
+/// that is not mandatory counts as missing
+pub fn check_parameters(expected: &[Parameter], parameters: &Map<String, Value>) -> Result<(), UnicomError>{
+    for parameter in expected{
+        match parameters.get(&parameter.name).filter(|value| parameter.mandatory || !value.is_null()){
+            Some(value) => {
+                if !parameter.check(value){
+                    return Err(UnicomError::new(UnicomErrorKind::ParameterInvalid, &format!("Wrong type of parameter {} {:?}", &parameter.name, value)))
+                }
+            },
+            None => {
+                if parameter.mandatory{
+                    return Err(UnicomError::new(UnicomErrorKind::ParameterInvalid, &format!("parameter {} is missing", &parameter.name)))
+                }
+            },
         }
-        Ok(())
     }
+    Ok(())
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -77,7 +97,8 @@ impl Parameter{
     fn check(&self, value: &Value) -> bool{
         match self.kind {
             ValueKind::Integer => value.is_i64(),
-            ValueKind::Float => value.is_f64(),
+            // json does not tell 2 from 2.0
+            ValueKind::Float => value.is_number(),
             ValueKind::String => value.is_string(),
             ValueKind::Url(_) => value.is_string(),
             ValueKind::Input => true,
@@ -88,35 +109,122 @@ impl Parameter{
     }
 }
 
+/// Parameters of an api method decoded into a struct, derived with `#[derive(UnicomParams)]`.
+///
+/// Fields of type `Option<T>` are not mandatory and accept null, the kind of a field comes from its type with
+/// `ParameterKind` or from `#[unicom(kind = "...")]` using the names of `ValueKind::from`.
+///
+/// ```no_run
+/// use std::sync::Arc;
+/// use unicom_lib::{ServerConnection, UnicomParams, unicom_api, context::RequestContext, error::UnicomError};
+///
+/// #[derive(UnicomParams)]
+/// struct Search{
+///     query: String,
+///     limit: Option<i64>,
+///     #[unicom(kind = "sid")]
+///     session: String,
+/// }
+///
+/// struct Library;
+///
+/// #[unicom_api(name = "library")]
+/// impl Library{
+///     #[get]
+///     async fn search(&self, params: Search, _context: &RequestContext) -> Result<Vec<u8>, UnicomError>{
+///         Ok(format!("{} {:?}", params.query, params.limit).into_bytes())
+///     }
+///
+///     #[delete]
+///     async fn clear(&self, _server: &Arc<ServerConnection>) -> Result<Vec<u8>, UnicomError>{
+///         Ok(Vec::new())
+///     }
+/// }
+/// ```
+pub trait UnicomParams: Sized{
+    /// Description of the fields, as sent to the hub
+    fn parameters() -> Vec<Parameter>;
+
+    /// Check `parameters` against the description and decode them
+    fn from_parameters(parameters: &Map<String, Value>) -> Result<Self, UnicomError>;
+}
+
+/// Kind announced to the hub for a parameter of this type
+pub trait ParameterKind{
+    fn kind() -> ValueKind;
+}
+
+macro_rules! parameter_kind {
+    ($kind:expr, $($ty:ty),*) => {
+        $(impl ParameterKind for $ty{
+            fn kind() -> ValueKind{
+                $kind
+            }
+        })*
+    };
+}
+
+parameter_kind!(ValueKind::Integer, i8, i16, i32, i64, u8, u16, u32, u64, isize, usize);
+parameter_kind!(ValueKind::Float, f32, f64);
+parameter_kind!(ValueKind::String, String);
+parameter_kind!(ValueKind::User, crate::User);
+// there is no boolean or list kind, any value is taken and the decoding checks it
+parameter_kind!(ValueKind::Input, bool, Value, Map<String, Value>);
+
+impl<T> ParameterKind for Vec<T>{
+    fn kind() -> ValueKind{
+        ValueKind::Input
+    }
+}
+
+/// Decode the parameter `name`, a missing parameter is decoded from null. Used by `#[derive(UnicomParams)]`
+#[doc(hidden)]
+pub fn decode_parameter<T: DeserializeOwned>(parameters: &Map<String, Value>, name: &str) -> Result<T, UnicomError>{
+    serde_json::from_value(parameters.get(name).cloned().unwrap_or(Value::Null))
+        .map_err(|e| UnicomError::new(UnicomErrorKind::ParameterInvalid, &format!("parameter {} error {}", name, e)))
+}
+
 #[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
 pub enum MethodKind{
     GET,
     PUT,
     POST,
     DELETE,
+    PATCH,
+    HEAD,
+    OPTIONS,
 }
 
-impl From<Method> for MethodKind{
-    fn from(m: Method) -> Self {
-        return m.as_str().into()
+impl TryFrom<Method> for MethodKind{
+    type Error = UnicomError;
+
+    fn try_from(m: Method) -> Result<Self, Self::Error> {
+        m.as_str().try_into()
     }
 }
 
-impl From<&str> for MethodKind{
-    fn from(m: &str) -> Self {
+impl TryFrom<&str> for MethodKind{
+    type Error = UnicomError;
+
+    fn try_from(m: &str) -> Result<Self, Self::Error> {
         match m {
-            "GET" => MethodKind::GET,
-            "PUT" => MethodKind::PUT,
-            "POST" => MethodKind::POST,
-            "DELETE" => MethodKind::DELETE,
-            _ => MethodKind::GET
+            "GET" => Ok(MethodKind::GET),
+            "PUT" => Ok(MethodKind::PUT),
+            "POST" => Ok(MethodKind::POST),
+            "DELETE" => Ok(MethodKind::DELETE),
+            "PATCH" => Ok(MethodKind::PATCH),
+            "HEAD" => Ok(MethodKind::HEAD),
+            "OPTIONS" => Ok(MethodKind::OPTIONS),
+            _ => Err(UnicomError::new(UnicomErrorKind::MethodNotAllowed, &format!("Methode {} not allowed", m))),
         }
     }
 }
 
-impl From<String> for MethodKind{
-    fn from(m: String) -> Self {
-        m.as_str().into()
+impl TryFrom<String> for MethodKind{
+    type Error = UnicomError;
+
+    fn try_from(m: String) -> Result<Self, Self::Error> {
+        m.as_str().try_into()
     }
 }
 
@@ -127,10 +235,18 @@ impl From<MethodKind> for &str{
             MethodKind::PUT => "PUT",
             MethodKind::POST => "POST",
             MethodKind::DELETE => "DELETE",
+            MethodKind::PATCH => "PATCH",
+            MethodKind::HEAD => "HEAD",
+            MethodKind::OPTIONS => "OPTIONS",
         }
     }
 }
 
+/// Body of the automatic answer to OPTIONS, the methods of the api with their parameters
+pub fn options_body(methods: &[ApiMethod]) -> Result<Vec<u8>, UnicomError>{
+    Ok(serde_json::to_vec(methods)?)
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub enum ValueKind{
     Integer,
@@ -167,10 +283,10 @@ impl From<&str> for ValueKind{
                 if let Ok(value) = v[1].parse(){
                     return ValueKind::Url(value)
                 }
-                return ValueKind::String
+                ValueKind::String
             }
             _ => {
-                return ValueKind::String
+                ValueKind::String
             }
         }
     }
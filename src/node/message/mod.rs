@@ -2,8 +2,10 @@ use self::request::UnicomRequest;
 
 pub mod request;
 pub mod response;
+pub mod stream;
 
 #[derive(Debug)]
+#[allow(clippy::large_enum_variant)]
 pub enum UnicomMessage{
     Request{
         id: u64,
@@ -11,4 +13,3 @@ pub enum UnicomMessage{
     },
     Quit,
 }
-
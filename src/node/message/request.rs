@@ -1,8 +1,14 @@
+use std::{fs::File, sync::Mutex, time::{Duration, Instant}};
+
+use serde::de::DeserializeOwned;
 use serde_json::{Map, Value};
 
+use crate::User;
 use crate::error::{UnicomError, UnicomErrorKind};
 
 use super::super::api::MethodKind;
+use super::response::ResponseHead;
+use super::stream::UnicomStream;
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct UnicomRequest{
@@ -11,6 +17,35 @@ pub struct UnicomRequest{
     pub node_name: String,
     pub method: MethodKind,
     pub parameters: Map<String,Value>,
+    #[serde(default)]
+    pub streamed: bool,
+    /// Time the caller waits for the answer in milliseconds, counted from when the request was sent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<u64>,
+    /// Node that sent the request, set by the hub
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub caller: Option<String>,
+    /// User the hub authenticated for this request
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<User>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trace_id: Option<String>,
+    #[serde(skip)]
+    deadline: Option<Instant>,
+    #[serde(skip)]
+    body: Mutex<Option<UnicomStream>>,
+    #[serde(skip)]
+    files: Mutex<Vec<File>>,
+    #[serde(skip)]
+    response_files: Mutex<Vec<File>>,
+    #[serde(skip)]
+    response_head: Mutex<ResponseHead>,
+}
+
+impl Default for UnicomRequest{
+    fn default() -> Self {
+        UnicomRequest::new()
+    }
 }
 
 impl UnicomRequest{
@@ -21,12 +56,26 @@ impl UnicomRequest{
             node_name: String::new(),
             method: MethodKind::GET,
             parameters: Map::new(),
+            streamed: false,
+            timeout: None,
+            caller: None,
+            user: None,
+            trace_id: None,
+            deadline: None,
+            body: Mutex::new(None),
+            files: Mutex::new(Vec::new()),
+            response_files: Mutex::new(Vec::new()),
+            response_head: Mutex::new(ResponseHead::default()),
         }
     }
     pub fn from_utf8(message: Vec<u8>) -> Result<UnicomRequest, UnicomError>{
         if let Ok(message) = String::from_utf8(message){
             match serde_json::from_str(&message) {
-                Ok(v) => Ok(v),
+                Ok(v) => {
+                    let mut request: UnicomRequest = v;
+                    request.deadline = request.timeout.map(|timeout| Instant::now() + Duration::from_millis(timeout));
+                    Ok(request)
+                },
                 Err(e) => Err(UnicomError::new(UnicomErrorKind::ParseError, &format!("read request error {:?} {}",e, message))),
             }
         }
@@ -34,8 +83,70 @@ impl UnicomRequest{
             Err(UnicomError::new(UnicomErrorKind::ParseError, "Could not parse body to String"))
         }
     }
-}
 
+    /// Decode the parameters into a typed struct
+    pub fn parameters_as<T: DeserializeOwned>(&self) -> Result<T, UnicomError>{
+        serde_json::from_value(Value::Object(self.parameters.clone()))
+            .map_err(|e| UnicomError::new(UnicomErrorKind::ParameterInvalid, &format!("parameters of {}/{} error {}", self.node_name, self.name, e)))
+    }
+
+    /// Instant after which the caller stops waiting for the answer, set on received requests
+    pub fn deadline(&self) -> Option<Instant>{
+        self.deadline
+    }
+
+    /// Time left to answer before the caller gives up, `None` when it waits forever
+    pub fn remaining(&self) -> Option<Duration>{
+        self.deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()))
+    }
+
+    /// Take the streamed body sent with this request, only available once
+    pub fn take_body(&self) -> Option<UnicomStream>{
+        self.body.lock().unwrap().take()
+    }
+
+    pub(crate) fn set_body(&self, body: UnicomStream){
+        *self.body.lock().unwrap() = Some(body);
+    }
+
+    /// Take the files passed by descriptor with this request, only available once
+    pub fn take_files(&self) -> Vec<File>{
+        std::mem::take(&mut self.files.lock().unwrap())
+    }
+
+    pub(crate) fn set_files(&self, files: Vec<File>){
+        *self.files.lock().unwrap() = files;
+    }
+
+    /// Pass a file by descriptor with the response to this request
+    pub fn add_response_file(&self, file: File){
+        self.response_files.lock().unwrap().push(file);
+    }
 
+    pub(crate) fn take_response_files(&self) -> Vec<File>{
+        std::mem::take(&mut self.response_files.lock().unwrap())
+    }
 
+    /// Http status of the response to this request
+    pub fn set_status(&self, status: u16){
+        self.response_head.lock().unwrap().status = Some(status);
+    }
 
+    /// Add a header to the response to this request
+    pub fn add_response_header(&self, name: &str, value: &str){
+        self.response_head.lock().unwrap().headers.push((name.to_owned(), value.to_owned()));
+    }
+
+    pub fn set_content_type(&self, content_type: &str){
+        self.response_head.lock().unwrap().content_type = Some(content_type.to_owned());
+    }
+
+    /// Status, headers and content type of the response to this request, sent before the answer of the handler
+    pub fn set_response_head(&self, head: ResponseHead){
+        *self.response_head.lock().unwrap() = head;
+    }
+
+    pub(crate) fn take_response_head(&self) -> ResponseHead{
+        std::mem::take(&mut self.response_head.lock().unwrap())
+    }
+}
@@ -1,24 +1,125 @@
+use hyper::{header::{HeaderName, HeaderValue, CONTENT_TYPE, LOCATION}, Body, Response, StatusCode};
 use serde_json::Value;
 
-use crate::error::UnicomError;
+use crate::error::{UnicomError, UnicomErrorKind};
 
-#[derive(Debug)]
+/// Status, headers and content type of a response. They travel in a head frame before the data,
+/// only when one of them is set
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct ResponseHead{
+    /// Http status code, 200 when not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<u16>,
+    /// Header names and values in order, a name can be repeated as for `Set-Cookie`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub headers: Vec<(String, String)>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>,
+}
+
+impl ResponseHead{
+    pub fn new() -> ResponseHead{
+        ResponseHead::default()
+    }
+
+    pub fn with_status(mut self, status: u16) -> ResponseHead{
+        self.status = Some(status);
+        self
+    }
+
+    /// Add a header, the ones already set with the same name are kept
+    pub fn with_header(mut self, name: &str, value: &str) -> ResponseHead{
+        self.headers.push((name.to_owned(), value.to_owned()));
+        self
+    }
+
+    pub fn with_content_type(mut self, content_type: &str) -> ResponseHead{
+        self.content_type = Some(content_type.to_owned());
+        self
+    }
+
+    /// Whether nothing is set, a response with an empty head is sent without head frame
+    pub fn is_empty(&self) -> bool{
+        self.status.is_none() && self.headers.is_empty() && self.content_type.is_none()
+    }
+
+    pub fn from_utf8(message: Vec<u8>) -> Result<ResponseHead, UnicomError>{
+        serde_json::from_slice(&message)
+            .map_err(|e| UnicomError::new(UnicomErrorKind::ParseError, &format!("read response head error {}", e)))
+    }
+}
+
+/// Answer of a node, its data and head
+#[derive(Debug, Default)]
 pub struct UnicomResponse{
     pub data: Vec<u8>,
+    pub head: ResponseHead,
 }
 
 
 impl UnicomResponse {
+    pub fn new(data: Vec<u8>) -> UnicomResponse{
+        UnicomResponse { data, ..Default::default() }
+    }
+
     pub fn empty() -> UnicomResponse{
-        UnicomResponse { data: "{}".into() }
+        UnicomResponse::new("{}".into())
     }
 
     pub fn from_json(data: &Value) -> Result<UnicomResponse, UnicomError>{
-        Ok(UnicomResponse { data: serde_json::to_string(data)?.as_bytes().to_vec() })
+        Ok(UnicomResponse::new(serde_json::to_string(data)?.as_bytes().to_vec()))
     }
 
     pub fn from_string(data: String) -> UnicomResponse{
-        UnicomResponse { data: data.as_bytes().to_vec() }
+        UnicomResponse::new(data.as_bytes().to_vec())
+    }
+
+    /// Empty answer sending the client to `location` with a 303 See Other
+    pub fn redirect(location: &str) -> UnicomResponse{
+        UnicomResponse::new(Vec::new()).with_status(StatusCode::SEE_OTHER.as_u16()).with_header(LOCATION.as_str(), location)
+    }
+
+    pub fn with_status(mut self, status: u16) -> UnicomResponse{
+        self.head = self.head.with_status(status);
+        self
+    }
+
+    /// Add a header, the ones already set with the same name are kept
+    pub fn with_header(mut self, name: &str, value: &str) -> UnicomResponse{
+        self.head = self.head.with_header(name, value);
+        self
+    }
+
+    pub fn with_content_type(mut self, content_type: &str) -> UnicomResponse{
+        self.head = self.head.with_content_type(content_type);
+        self
+    }
+
+    fn try_into_response(self) -> Result<Response<Body>, UnicomError>{
+        let status = StatusCode::from_u16(self.head.status.unwrap_or(200))
+            .map_err(|e| UnicomError::new(UnicomErrorKind::DataInvalid, &format!("response status error {}", e)))?;
+        let mut response = Response::new(Body::from(self.data));
+        *response.status_mut() = status;
+        let headers = response.headers_mut();
+        for (name, value) in self.head.headers{
+            let name = HeaderName::from_bytes(name.as_bytes())
+                .map_err(|e| UnicomError::new(UnicomErrorKind::DataInvalid, &format!("response header {} error {}", name, e)))?;
+            let value = HeaderValue::from_str(&value)
+                .map_err(|e| UnicomError::new(UnicomErrorKind::DataInvalid, &format!("response header {} error {}", name, e)))?;
+            headers.append(name, value);
+        }
+        if let Some(content_type) = self.head.content_type{
+            let value = HeaderValue::from_str(&content_type)
+                .map_err(|e| UnicomError::new(UnicomErrorKind::DataInvalid, &format!("response content type error {}", e)))?;
+            headers.insert(CONTENT_TYPE, value);
+        }
+        Ok(response)
     }
 }
 
+impl From<UnicomResponse> for Response<Body>{
+    /// A status or header hyper refuses turns into the error response
+    fn from(response: UnicomResponse) -> Self {
+        response.try_into_response().unwrap_or_else(|error| error.into())
+    }
+}
@@ -0,0 +1,131 @@
+use std::{future::Future, pin::Pin, task::{Context, Poll}, time::Duration};
+
+use bytes::Bytes;
+use futures::{Stream, StreamExt};
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tokio::time::Sleep;
+
+use crate::arch::unix::{ChunkOrigin, INITIAL_WINDOW};
+use crate::error::{UnicomError, UnicomErrorKind};
+use crate::node::utils::pending::PendingGuard;
+
+pub type BodyStream = Pin<Box<dyn Stream<Item = Result<Bytes, UnicomError>> + Send>>;
+pub type BodySender = mpsc::Sender<Result<Bytes, UnicomError>>;
+/// Credit granted back to the sender of a body, written as window frames
+pub type WindowSender = mpsc::UnboundedSender<(u64, ChunkOrigin, u32)>;
+
+/// Number of chunks buffered for the consumer. With flow control the sender never has more in flight,
+/// without it a body falling further behind is failed so the connection reader never waits on it
+const BODY_BUFFER: usize = INITIAL_WINDOW;
+
+/// Hand a chunk to the consumer without waiting, a full buffer fails the body with `Busy` and
+/// returns the error. The consumer gone is not an error, the chunk is dropped
+pub(crate) fn push_body(sender: BodySender, data: Bytes) -> Result<(), UnicomError>{
+    match sender.try_send(Ok(data)){
+        Err(TrySendError::Full(_)) => {
+            let error = UnicomError::new(UnicomErrorKind::Busy, "body not read fast enough");
+            end_body(sender, Err(error.clone()));
+            Err(error)
+        },
+        _ => Ok(()),
+    }
+}
+
+/// Hand the last item of a body to the consumer, it waits in a task of its own when the buffer is full
+pub(crate) fn end_body(sender: BodySender, item: Result<Bytes, UnicomError>){
+    if let Err(TrySendError::Full(item)) = sender.try_send(item){
+        tokio::spawn(async move {
+            let _ = sender.send(item).await;
+        });
+    }
+}
+
+/// Receiving side of a body streamed in chunk frames
+#[derive(Debug)]
+pub struct UnicomStream{
+    receiver: mpsc::Receiver<Result<Bytes, UnicomError>>,
+    guard: Option<PendingGuard>,
+    timeout: Option<(Duration, Pin<Box<Sleep>>)>,
+    expired: bool,
+    window: Option<Window>,
+}
+
+/// Chunks read since credit was last granted for the body `id`
+#[derive(Debug)]
+struct Window{
+    id: u64,
+    origin: ChunkOrigin,
+    sender: WindowSender,
+    consumed: u32,
+}
+
+impl UnicomStream{
+    pub fn channel() -> (BodySender, UnicomStream){
+        let (sender, receiver) = mpsc::channel(BODY_BUFFER);
+        (sender, UnicomStream { receiver, guard: None, timeout: None, expired: false, window: None })
+    }
+
+    /// Cancel the request when the stream is dropped before its end
+    pub(crate) fn set_guard(&mut self, guard: PendingGuard){
+        self.guard = Some(guard);
+    }
+
+    /// Fail the stream with `Timeout` and cancel the request when it has not ended after `timeout`
+    pub(crate) fn set_timeout(&mut self, timeout: Duration){
+        self.timeout = Some((timeout, Box::pin(tokio::time::sleep(timeout))));
+    }
+
+    /// Grant the sender of the body `id` more credit as chunks are read, once flow control is negotiated
+    pub(crate) fn set_window(&mut self, id: u64, origin: ChunkOrigin, sender: WindowSender){
+        self.window = Some(Window { id, origin, sender, consumed: 0 });
+    }
+
+    pub async fn to_vec(mut self) -> Result<Vec<u8>, UnicomError>{
+        let mut data = Vec::new();
+        while let Some(chunk) = self.next().await{
+            data.extend_from_slice(&chunk?);
+        }
+        Ok(data)
+    }
+}
+
+impl Stream for UnicomStream{
+    type Item = Result<Bytes, UnicomError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if self.expired{
+            return Poll::Ready(None)
+        }
+        let next = self.receiver.poll_recv(cx);
+        match next{
+            Poll::Ready(Some(Ok(_))) => {
+                // credit goes back in batches, half a buffer at a time
+                if let Some(window) = self.window.as_mut(){
+                    window.consumed += 1;
+                    if window.consumed as usize >= BODY_BUFFER / 2{
+                        let _ = window.sender.send((window.id, window.origin, window.consumed));
+                        window.consumed = 0;
+                    }
+                }
+            },
+            Poll::Ready(None) => {
+                if let Some(guard) = self.guard.as_mut(){
+                    guard.disarm();
+                }
+            },
+            Poll::Pending => {
+                if let Some((timeout, sleep)) = self.timeout.as_mut(){
+                    if sleep.as_mut().poll(cx).is_ready(){
+                        let error = UnicomError::new(UnicomErrorKind::Timeout, &format!("stream not ended after {:?}", timeout));
+                        // the guard drops armed and cancels the request
+                        self.guard = None;
+                        self.expired = true;
+                        return Poll::Ready(Some(Err(error)))
+                    }
+                }
+            },
+            _ => (),
+        }
+        next
+    }
+}
@@ -4,7 +4,7 @@ use serde_json::{Map, Value};
 
 use crate::error::{UnicomError, UnicomErrorKind};
 
-use self::{ api::{Api, MethodKind, ApiMethod}, message::{request::UnicomRequest, UnicomMessage}, 
+use self::{ api::{Api, ApiUpdate, MethodKind, ApiMethod}, message::{request::UnicomRequest, UnicomMessage}, 
         message::response::UnicomResponse, endpoint::{EndPoint, Template, EndPointKind}};
 
 use async_trait::async_trait;
@@ -46,21 +46,20 @@ impl Node{
     }
 
     pub async fn request(&self, api: &Api, method: MethodKind, parameters: Map<String, Value>) -> Result<UnicomResponse, UnicomError>{
-        api.get_method(&method)?.generate_parameters(&parameters)?;
-
-        Ok(self.connector.request(UnicomRequest{
-            id: api.id,
-            parameters,
-            method,
-            name: String::new(),
-            node_name: String::new(),
-        }).await?)
+        // every api answers OPTIONS, it takes no parameters
+        if method != MethodKind::OPTIONS{
+            api.get_method(&method)?.generate_parameters(&parameters)?;
+        }
+
+        let mut request = UnicomRequest::new();
+        request.id = api.id;
+        request.parameters = parameters;
+        request.method = method;
+        self.connector.request(request).await
     }
 
     pub async fn response(&self, request_id: u64, data: Vec<u8>) -> Result<(), UnicomError> {
-        Ok(self.connector.response(request_id, UnicomResponse{
-            data,
-        }).await?)
+        self.connector.response(request_id, UnicomResponse::new(data)).await
     }
 
     pub async fn error(&self, request_id: u64, error: UnicomError) -> Result<(), UnicomError>{
@@ -105,6 +104,17 @@ impl NodeConfig{
         Ok(serde_json::from_str(&String::from_utf8(message)?)?)
     }
 
+    /// Apply an api update sent by a running node
+    pub fn update_api(&mut self, update: ApiUpdate){
+        match update{
+            ApiUpdate::Added(api) => {
+                self.api.retain(|current| current.id != api.id);
+                self.api.push(api);
+            },
+            ApiUpdate::Removed { id } => self.api.retain(|current| current.id != id),
+        }
+    }
+
     pub fn add_api(&mut self, id: u64, name: &str, methods : Vec<ApiMethod>){
         self.api.push(Api::new(id, name, methods))
     }
@@ -0,0 +1,85 @@
+use std::sync::Arc;
+
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::error::{UnicomError, UnicomErrorKind};
+use crate::middleware::UnicomMiddleware;
+
+/// How many requests an api handles at once, requests beyond `max_concurrency` wait in a queue of
+/// `queue` places and the ones finding it full are answered with a `Busy` error
+#[derive(Clone, Default)]
+pub struct ApiOptions{
+    /// `None` runs every request as soon as it arrives
+    pub max_concurrency: Option<usize>,
+    pub queue: usize,
+    /// Run around this api only, after the middleware of the connection
+    pub middleware: Vec<Arc<dyn UnicomMiddleware>>,
+}
+
+impl ApiOptions{
+    pub fn new(max_concurrency: usize, queue: usize) -> ApiOptions{
+        ApiOptions { max_concurrency: Some(max_concurrency), queue, middleware: Vec::new() }
+    }
+
+    pub fn with_middleware(mut self, middleware: Arc<dyn UnicomMiddleware>) -> ApiOptions{
+        self.middleware.push(middleware);
+        self
+    }
+
+    /// Refuse a `max_concurrency` of 0, its requests would wait in the queue forever
+    pub fn check(&self) -> Result<(), UnicomError>{
+        if self.max_concurrency == Some(0){
+            return Err(UnicomError::new(UnicomErrorKind::ParameterInvalid, "api max_concurrency must be at least 1"))
+        }
+        Ok(())
+    }
+}
+
+pub struct ApiLimit{
+    running: Arc<Semaphore>,
+    waiting: Arc<Semaphore>,
+}
+
+/// Place of an admitted request, running or waiting for a running slot
+pub enum Admission{
+    Running(OwnedSemaphorePermit),
+    Queued{
+        place: OwnedSemaphorePermit,
+        running: Arc<Semaphore>,
+    },
+}
+
+impl ApiLimit{
+    pub fn new(options: &ApiOptions) -> Option<ApiLimit>{
+        let max_concurrency = options.max_concurrency?;
+        Some(ApiLimit {
+            running: Arc::new(Semaphore::new(max_concurrency)),
+            waiting: Arc::new(Semaphore::new(options.queue)),
+        })
+    }
+
+    /// Take a running slot or a place in the queue, `Busy` when both are full
+    pub fn admit(&self, api: &str) -> Result<Admission, UnicomError>{
+        if let Ok(permit) = self.running.clone().try_acquire_owned(){
+            return Ok(Admission::Running(permit))
+        }
+        match self.waiting.clone().try_acquire_owned(){
+            Ok(place) => Ok(Admission::Queued { place, running: self.running.clone() }),
+            Err(_) => Err(UnicomError::new(UnicomErrorKind::Busy, &format!("api {} overloaded", api))),
+        }
+    }
+}
+
+impl Admission{
+    /// Wait for a running slot, the queue place is given back once it is obtained
+    pub async fn run(self) -> OwnedSemaphorePermit{
+        match self{
+            Admission::Running(permit) => permit,
+            Admission::Queued { place, running } => {
+                let permit = running.acquire_owned().await.expect("api limit semaphore closed");
+                drop(place);
+                permit
+            },
+        }
+    }
+}
@@ -1 +1,2 @@
-pub mod pending;
\ No newline at end of file
+pub mod pending;
+pub mod limit;
\ No newline at end of file
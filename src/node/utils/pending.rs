@@ -1,12 +1,15 @@
-use std::sync::Arc;
+use std::{fs::File, sync::Arc};
 
-use tokio::sync::{Notify, Mutex};
+use bytes::Bytes;
+use tokio::sync::{mpsc, oneshot, Notify, Mutex};
 
 use crate::error::{UnicomError, UnicomErrorKind};
+use crate::node::message::{response::{ResponseHead, UnicomResponse}, stream::{end_body, push_body, BodySender, UnicomStream}};
 
 #[derive(Debug)]
 enum PendingState{
     Pending,
+    Streaming(Vec<u8>),
     Ok(Vec<u8>),
     Error(UnicomError),
 }
@@ -16,6 +19,52 @@ struct Pending{
     id: u64,
     state: PendingState,
     notify: Arc<Notify>,
+    stream: Option<BodySender>,
+    files: Vec<File>,
+    head: ResponseHead,
+    /// Caller of a streamed request waiting for the head, or the error answered instead
+    head_sender: Option<oneshot::Sender<Result<ResponseHead, UnicomError>>>,
+}
+
+pub type HeadReceiver = oneshot::Receiver<Result<ResponseHead, UnicomError>>;
+
+impl Pending{
+    /// Hand the head, or `error` when the answer is one, to the caller of a streamed request once the
+    /// answer starts, only the first call counts
+    fn send_head(&mut self, error: Option<UnicomError>){
+        if let Some(sender) = self.head_sender.take(){
+            let _ = sender.send(match error{
+                Some(error) => Err(error),
+                None => Ok(std::mem::take(&mut self.head)),
+            });
+        }
+    }
+}
+
+/// Cancels a pending request when dropped before its response arrived
+#[derive(Debug)]
+pub struct PendingGuard{
+    id: u64,
+    cancel: mpsc::UnboundedSender<u64>,
+    armed: bool,
+}
+
+impl PendingGuard{
+    pub fn new(id: u64, cancel: mpsc::UnboundedSender<u64>) -> PendingGuard{
+        PendingGuard { id, cancel, armed: true }
+    }
+
+    pub fn disarm(&mut self){
+        self.armed = false;
+    }
+}
+
+impl Drop for PendingGuard{
+    fn drop(&mut self) {
+        if self.armed{
+            let _ = self.cancel.send(self.id);
+        }
+    }
 }
 
 pub struct PendingController{
@@ -23,53 +72,183 @@ pub struct PendingController{
     pending: Mutex<Vec<Pending>>
 }
 
+impl Default for PendingController{
+    fn default() -> Self {
+        PendingController::new()
+    }
+}
+
 impl PendingController{
     pub fn new() -> PendingController{
-        PendingController { 
-            counter: Mutex::new(0), 
+        PendingController {
+            counter: Mutex::new(0),
             pending: Mutex::new(Vec::new())
          }
     }
 
-    pub async fn create(&self) -> (u64, Arc<Notify>){
+    async fn next_id(&self) -> u64{
         let mut counter = self.counter.lock().await;
         *counter += 1;
-        let id = counter.clone();
-        drop(counter);
+        *counter
+    }
+
+    pub async fn create(&self) -> (u64, Arc<Notify>){
+        let id = self.next_id().await;
         let pending = Pending{
             id,
             state: PendingState::Pending,
             notify: Arc::new(Notify::new()),
+            stream: None,
+            files: Vec::new(),
+            head: ResponseHead::default(),
+            head_sender: None,
         };
         let notify = pending.notify.clone();
         self.pending.lock().await.push(pending);
         (id, notify)
     }
 
+    /// Create a pending request whose response is forwarded chunk by chunk to the returned stream, its head
+    /// is received once the answer starts
+    pub async fn create_stream(&self) -> (u64, HeadReceiver, UnicomStream){
+        let id = self.next_id().await;
+        let (sender, stream) = UnicomStream::channel();
+        let (head_sender, head) = oneshot::channel();
+        self.pending.lock().await.push(Pending{
+            id,
+            state: PendingState::Pending,
+            notify: Arc::new(Notify::new()),
+            stream: Some(sender),
+            files: Vec::new(),
+            head: ResponseHead::default(),
+            head_sender: Some(head_sender),
+        });
+        (id, head, stream)
+    }
+
+    /// Complete a pending request, the answer to an unknown or expired request is dropped
     pub async fn update(&self, id: u64, value: Result<Vec<u8>, UnicomError>) -> Result<(), UnicomError>{
         let mut pending = self.pending.lock().await;
         if let Some(index) = pending.iter().position(|response| response.id == id){
+            if pending[index].stream.is_some(){
+                let mut current = pending.remove(index);
+                current.send_head(value.as_ref().err().cloned());
+                if let Some(sender) = current.stream{
+                    end_body(sender, value.map(Bytes::from));
+                }
+                return Ok(())
+            }
             let current = &mut pending[index];
             current.state = match value{
                 Ok(data) => PendingState::Ok(data),
                 Err(e) => PendingState::Error(e),
             };
             current.notify.notify_one();
-            Ok(())
         }
-        else{
-            Err(UnicomError::new(UnicomErrorKind::InputInvalid, "pending unknown"))
+        Ok(())
+    }
+
+    /// Add a chunk to a streamed response, an empty chunk completes it. A stream whose consumer is too
+    /// far behind, which flow control prevents, is failed and forgotten, the error tells the caller to
+    /// cancel the request
+    pub async fn push_chunk(&self, id: u64, data: Vec<u8>) -> Result<(), UnicomError>{
+        let mut pending = self.pending.lock().await;
+        if let Some(index) = pending.iter().position(|response| response.id == id){
+            if let Some(sender) = pending[index].stream.clone(){
+                pending[index].send_head(None);
+                if data.is_empty(){
+                    pending.remove(index);
+                    return Ok(())
+                }
+                let pushed = push_body(sender, Bytes::from(data));
+                if pushed.is_err(){
+                    pending.remove(index);
+                }
+                return pushed
+            }
+            let current = &mut pending[index];
+            let mut buffer = match std::mem::replace(&mut current.state, PendingState::Pending){
+                PendingState::Streaming(buffer) => buffer,
+                _ => Vec::new(),
+            };
+            if data.is_empty(){
+                current.state = PendingState::Ok(buffer);
+                current.notify.notify_one();
+            }
+            else{
+                buffer.extend_from_slice(&data);
+                current.state = PendingState::Streaming(buffer);
+            }
         }
+        Ok(())
+    }
+
+    /// Complete every pending request with the same error, used when the connection is lost
+    pub async fn fail_all(&self, error: UnicomError){
+        let mut pending = self.pending.lock().await;
+        pending.retain_mut(|current| {
+            if let Some(sender) = current.stream.take(){
+                current.send_head(Some(error.clone()));
+                end_body(sender, Err(error.clone()));
+                return false
+            }
+            if let PendingState::Pending | PendingState::Streaming(_) = current.state{
+                current.state = PendingState::Error(error.clone());
+                current.notify.notify_one();
+            }
+            true
+        });
+    }
+
+    /// Forget a pending request, a response arriving later is dropped
+    pub async fn remove(&self, id: u64) -> bool{
+        let mut pending = self.pending.lock().await;
+        match pending.iter().position(|response| response.id == id){
+            Some(index) => {
+                pending.remove(index);
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Keep the files passed by descriptor with the response to a pending request, they are closed when it is unknown
+    pub async fn attach_files(&self, id: u64, files: Vec<File>) -> Result<(), UnicomError>{
+        let mut pending = self.pending.lock().await;
+        if let Some(current) = pending.iter_mut().find(|response| response.id == id){
+            current.files.extend(files);
+        }
+        Ok(())
+    }
+
+    /// Keep the status, headers and content type of the response to a pending request
+    pub async fn attach_head(&self, id: u64, head: ResponseHead) -> Result<(), UnicomError>{
+        let mut pending = self.pending.lock().await;
+        if let Some(current) = pending.iter_mut().find(|response| response.id == id){
+            current.head = head;
+            current.send_head(None);
+        }
+        Ok(())
     }
 
     pub async fn get(&self, id: u64) -> Result<Vec<u8>, UnicomError>{
+        Ok(self.get_with_files(id).await?.0)
+    }
+
+    pub async fn get_with_files(&self, id: u64) -> Result<(Vec<u8>, Vec<File>), UnicomError>{
+        let (response, files) = self.get_response(id).await?;
+        Ok((response.data, files))
+    }
+
+    /// Answer with its status, headers and content type, and the files passed with it
+    pub async fn get_response(&self, id: u64) -> Result<(UnicomResponse, Vec<File>), UnicomError>{
         let mut pending = self.pending.lock().await;
         if let Some(index) = pending.iter().position(|response| response.id == id){
             let current = pending.remove(index);
             match current.state{
-                PendingState::Pending => Err(UnicomError::new(UnicomErrorKind::Internal, "still pending")),
+                PendingState::Pending | PendingState::Streaming(_) => Err(UnicomError::new(UnicomErrorKind::Internal, "still pending")),
                 PendingState::Ok(data) => {
-                    Ok(data)
+                    Ok((UnicomResponse { data, head: current.head }, current.files))
                 },
                 PendingState::Error(e) => Err(e),
             }
@@ -78,4 +257,4 @@ impl PendingController{
             Err(UnicomError::new(UnicomErrorKind::Internal, "pending unknown"))
         }
     }
-}
\ No newline at end of file
+}
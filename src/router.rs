@@ -0,0 +1,127 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use futures::future::BoxFuture;
+
+use crate::context::RequestContext;
+use crate::error::{UnicomError, UnicomErrorKind};
+use crate::node::{api::{options_body, ApiMethod, MethodKind, Parameter}, message::request::UnicomRequest};
+use crate::{ServerConnection, UnicomApi};
+
+/// Handler of one method of a `Router`
+pub type RouteHandler = Box<dyn for<'a> Fn(&'a Arc<ServerConnection>, &'a UnicomRequest, &'a RequestContext) -> BoxFuture<'a, Result<Vec<u8>, UnicomError>> + Send + Sync>;
+
+/// Api made of one handler per method, its description is generated from the registered methods and
+/// the other methods are answered `MethodNotAllowed`. Without their own handler HEAD runs GET and
+/// OPTIONS answers the description.
+///
+/// ```no_run
+/// use std::sync::Arc;
+/// use unicom_lib::{node::api::{Parameter, ValueKind}, router::Router};
+///
+/// let api = Router::new("hello")
+///     .get(vec![Parameter::new("name", ValueKind::String, true)], |_server, request, _context| Box::pin(async move {
+///         let name = request.parameters.get("name").and_then(|name| name.as_str()).unwrap_or("world");
+///         Ok(format!("hello {}", name).into_bytes())
+///     }));
+/// let api = Arc::new(api);
+/// ```
+pub struct Router{
+    name: String,
+    routes: Vec<(ApiMethod, RouteHandler)>,
+}
+
+impl Router{
+    pub fn new(name: &str) -> Router{
+        Router { name: name.to_owned(), routes: Vec::new() }
+    }
+
+    /// Answer `method` with `handler`, a method registered twice keeps the last handler
+    pub fn route<F>(mut self, method: MethodKind, parameters: Vec<Parameter>, handler: F) -> Router
+    where F: for<'a> Fn(&'a Arc<ServerConnection>, &'a UnicomRequest, &'a RequestContext) -> BoxFuture<'a, Result<Vec<u8>, UnicomError>> + Send + Sync + 'static{
+        self.routes.retain(|(api_method, _)| api_method.method != method);
+        self.routes.push((ApiMethod::new(method, parameters), Box::new(handler)));
+        self
+    }
+
+    pub fn get<F>(self, parameters: Vec<Parameter>, handler: F) -> Router
+    where F: for<'a> Fn(&'a Arc<ServerConnection>, &'a UnicomRequest, &'a RequestContext) -> BoxFuture<'a, Result<Vec<u8>, UnicomError>> + Send + Sync + 'static{
+        self.route(MethodKind::GET, parameters, handler)
+    }
+
+    pub fn put<F>(self, parameters: Vec<Parameter>, handler: F) -> Router
+    where F: for<'a> Fn(&'a Arc<ServerConnection>, &'a UnicomRequest, &'a RequestContext) -> BoxFuture<'a, Result<Vec<u8>, UnicomError>> + Send + Sync + 'static{
+        self.route(MethodKind::PUT, parameters, handler)
+    }
+
+    pub fn post<F>(self, parameters: Vec<Parameter>, handler: F) -> Router
+    where F: for<'a> Fn(&'a Arc<ServerConnection>, &'a UnicomRequest, &'a RequestContext) -> BoxFuture<'a, Result<Vec<u8>, UnicomError>> + Send + Sync + 'static{
+        self.route(MethodKind::POST, parameters, handler)
+    }
+
+    pub fn delete<F>(self, parameters: Vec<Parameter>, handler: F) -> Router
+    where F: for<'a> Fn(&'a Arc<ServerConnection>, &'a UnicomRequest, &'a RequestContext) -> BoxFuture<'a, Result<Vec<u8>, UnicomError>> + Send + Sync + 'static{
+        self.route(MethodKind::DELETE, parameters, handler)
+    }
+
+    pub fn patch<F>(self, parameters: Vec<Parameter>, handler: F) -> Router
+    where F: for<'a> Fn(&'a Arc<ServerConnection>, &'a UnicomRequest, &'a RequestContext) -> BoxFuture<'a, Result<Vec<u8>, UnicomError>> + Send + Sync + 'static{
+        self.route(MethodKind::PATCH, parameters, handler)
+    }
+
+    fn handler(&self, method: &MethodKind) -> Option<&RouteHandler>{
+        self.routes.iter().find(|(api_method, _)| api_method.method == *method).map(|(_, handler)| handler)
+    }
+
+    async fn call(&self, method: MethodKind, server: &Arc<ServerConnection>, request: &UnicomRequest, context: &RequestContext) -> Result<Vec<u8>, UnicomError>{
+        if let Some(handler) = self.handler(&method){
+            return handler(server, request, context).await
+        }
+        match method{
+            MethodKind::HEAD if self.handler(&MethodKind::GET).is_some() => {
+                Box::pin(self.call(MethodKind::GET, server, request, context)).await.map(|_| Vec::new())
+            },
+            MethodKind::OPTIONS => options_body(&self.description()),
+            method => Err(UnicomError::new(UnicomErrorKind::MethodNotAllowed, &format!("method {:?} not allowed on {}", method, self.name))),
+        }
+    }
+}
+
+#[async_trait]
+impl UnicomApi for Router{
+    fn name(&self) -> String{
+        self.name.clone()
+    }
+
+    fn description(&self) -> Vec<ApiMethod>{
+        self.routes.iter().map(|(api_method, _)| api_method.clone()).collect()
+    }
+
+    async fn api_get(&self, server: &Arc<ServerConnection>, request: &UnicomRequest, context: &RequestContext) -> Result<Vec<u8>, UnicomError>{
+        self.call(MethodKind::GET, server, request, context).await
+    }
+
+    async fn api_put(&self, server: &Arc<ServerConnection>, request: &UnicomRequest, context: &RequestContext) -> Result<Vec<u8>, UnicomError>{
+        self.call(MethodKind::PUT, server, request, context).await
+    }
+
+    async fn api_post(&self, server: &Arc<ServerConnection>, request: &UnicomRequest, context: &RequestContext) -> Result<Vec<u8>, UnicomError>{
+        self.call(MethodKind::POST, server, request, context).await
+    }
+
+    async fn api_delete(&self, server: &Arc<ServerConnection>, request: &UnicomRequest, context: &RequestContext) -> Result<Vec<u8>, UnicomError>{
+        self.call(MethodKind::DELETE, server, request, context).await
+    }
+
+    async fn api_patch(&self, server: &Arc<ServerConnection>, request: &UnicomRequest, context: &RequestContext) -> Result<Vec<u8>, UnicomError>{
+        self.call(MethodKind::PATCH, server, request, context).await
+    }
+
+    async fn api_head(&self, server: &Arc<ServerConnection>, request: &UnicomRequest, context: &RequestContext) -> Result<Vec<u8>, UnicomError>{
+        self.call(MethodKind::HEAD, server, request, context).await
+    }
+
+    async fn api_options(&self, server: &Arc<ServerConnection>, request: &UnicomRequest, context: &RequestContext) -> Result<Vec<u8>, UnicomError>{
+        self.call(MethodKind::OPTIONS, server, request, context).await
+    }
+}
@@ -0,0 +1,213 @@
+use std::{collections::{HashMap, VecDeque}, sync::Arc};
+
+use serde_json::{Map, Value};
+use tokio::io::{duplex, split, DuplexStream, ReadHalf, WriteHalf};
+use tokio::sync::Notify;
+
+use crate::arch::{Connection, memory::{MemoryListener, PIPE_SIZE}};
+use crate::arch::unix::{read_init, read_message, write_handshake, write_message, Capabilities, ChunkOrigin, Handshake, UnixMessage, UnixReader, UnixWriter, CHUNK_SIZE, INITIAL_WINDOW};
+use crate::error::{UnicomError, UnicomErrorKind};
+use crate::node::{api::MethodKind, message::{request::UnicomRequest, response::UnicomResponse}, NodeConfig};
+use crate::ServerConnection;
+
+/// Hub side of an in-memory connection, sends requests to a node and returns what it answers.
+///
+/// ```no_run
+/// # async fn test(api: std::sync::Arc<dyn unicom_lib::UnicomApi>) -> Result<(), unicom_lib::error::UnicomError>{
+/// use std::sync::Arc;
+/// use unicom_lib::{ServerConnection, config::Manifest, node::api::MethodKind, testing::FakeHub};
+///
+/// let mut server = ServerConnection::from_manifest("memory://node".parse()?, Manifest::new("node"))?;
+/// server.add_api(api);
+/// let server = Arc::new(server);
+/// let (mut hub, _notify) = FakeHub::start(&server).await?;
+/// let data = hub.call("my_api", MethodKind::GET, serde_json::Map::new()).await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct FakeHub{
+    reader: UnixReader<ReadHalf<DuplexStream>>,
+    writer: UnixWriter<WriteHalf<DuplexStream>>,
+    pub config: NodeConfig,
+    pub handshake: Handshake,
+    counter: u64,
+    /// Frames read while waiting for the answer to another request
+    buffered: VecDeque<UnixMessage>,
+    /// Chunks the node still takes for each body the hub sends, with flow control
+    credit: HashMap<(u64, ChunkOrigin), usize>,
+}
+
+impl FakeHub{
+    /// Accept the next node connecting to `listener` and answer its init frame
+    pub async fn accept(listener: &mut MemoryListener) -> Result<FakeHub, UnicomError>{
+        let (reader, writer) = listener.accept().await?;
+        FakeHub::handshake(reader, writer).await
+    }
+
+    /// Answer the init frame of the node on the other end of `stream`
+    pub async fn from_stream(stream: DuplexStream) -> Result<FakeHub, UnicomError>{
+        let (reader, writer) = split(stream);
+        FakeHub::handshake(reader, writer).await
+    }
+
+    async fn handshake(reader: ReadHalf<DuplexStream>, writer: WriteHalf<DuplexStream>) -> Result<FakeHub, UnicomError>{
+        let (mut reader, mut writer) = (UnixReader::new(reader), UnixWriter::new(writer));
+        let (config, handshake) = read_init(&mut reader, &Handshake::default()).await?;
+        write_handshake(&mut writer, &handshake).await?;
+        Ok(FakeHub { reader, writer, config, handshake, counter: 0, buffered: VecDeque::new(), credit: HashMap::new() })
+    }
+
+    /// Run `server` against a fake hub over a private in-memory pipe, its address is not used
+    pub async fn start(server: &Arc<ServerConnection>) -> Result<(FakeHub, Arc<Notify>), UnicomError>{
+        let (node, hub) = duplex(PIPE_SIZE);
+        let (reader, writer) = split(node);
+        let (notify, hub) = tokio::join!(ServerConnection::run_on(server, Connection::new(reader, writer)), FakeHub::from_stream(hub));
+        Ok((hub?, notify))
+    }
+
+    pub fn api_id(&self, name: &str) -> Option<u64>{
+        self.config.api.iter().find(|api| api.name == name).map(|api| api.id)
+    }
+
+    pub async fn send(&mut self, message: UnixMessage) -> Result<(), UnicomError>{
+        write_message(&mut self.writer, message).await
+    }
+
+    /// Next frame from the node, the ones set aside by `response` come first. Pings are answered on
+    /// the way, window frames added to the credit of `send_body` and api updates applied to `config`
+    pub async fn next(&mut self) -> Result<UnixMessage, UnicomError>{
+        match self.buffered.pop_front(){
+            Some(message) => Ok(message),
+            None => self.read().await,
+        }
+    }
+
+    async fn read(&mut self) -> Result<UnixMessage, UnicomError>{
+        loop{
+            if let Some(message) = self.read_frame().await?{
+                return Ok(message)
+            }
+        }
+    }
+
+    /// Read one frame, `None` when it was handled here
+    async fn read_frame(&mut self) -> Result<Option<UnixMessage>, UnicomError>{
+        match read_message(&mut self.reader).await?{
+            UnixMessage::Ping { id } => self.send(UnixMessage::Pong { id }).await?,
+            UnixMessage::Window { id, origin, credit } => {
+                *self.credit.entry((id, origin)).or_insert(INITIAL_WINDOW) += credit as usize;
+            },
+            UnixMessage::Apis { update } => {
+                self.config.update_api(update.clone());
+                return Ok(Some(UnixMessage::Apis { update }))
+            },
+            message => return Ok(Some(message)),
+        }
+        Ok(None)
+    }
+
+    fn flow_control(&self) -> bool{
+        self.handshake.capabilities.contains(Capabilities::FLOW_CONTROL)
+    }
+
+    /// Send a request to an api of the node and return its id
+    pub async fn request(&mut self, api: &str, method: MethodKind, parameters: Map<String, Value>) -> Result<u64, UnicomError>{
+        self.send_request(api, method, parameters, false).await
+    }
+
+    /// Send a request whose body follows with `send_body`, the handler reads it with `UnicomRequest::take_body`
+    pub async fn request_with_body(&mut self, api: &str, method: MethodKind, parameters: Map<String, Value>) -> Result<u64, UnicomError>{
+        self.send_request(api, method, parameters, true).await
+    }
+
+    async fn send_request(&mut self, api: &str, method: MethodKind, parameters: Map<String, Value>, streamed: bool) -> Result<u64, UnicomError>{
+        let mut data = UnicomRequest::new();
+        data.streamed = streamed;
+        data.id = self.api_id(api).ok_or_else(|| UnicomError::new(UnicomErrorKind::NotFound, &format!("api {} not registered", api)))?;
+        data.node_name = self.config.name.clone();
+        data.name = api.to_string();
+        data.method = method;
+        data.parameters = parameters;
+        self.counter += 1;
+        let id = self.counter;
+        self.send(UnixMessage::Request { id, data }).await?;
+        Ok(id)
+    }
+
+    /// Send `data` as chunks of the body of request `id`, without the end marker so more can follow.
+    /// With flow control it waits for the node to grant credit, the frames read meanwhile are kept for `next`
+    pub async fn send_body(&mut self, id: u64, data: &[u8]) -> Result<(), UnicomError>{
+        for chunk in data.chunks(CHUNK_SIZE){
+            if self.flow_control(){
+                while self.credit.get(&(id, ChunkOrigin::Request)).copied().unwrap_or(INITIAL_WINDOW) == 0{
+                    if let Some(message) = self.read_frame().await?{
+                        self.buffered.push_back(message);
+                    }
+                }
+                *self.credit.entry((id, ChunkOrigin::Request)).or_insert(INITIAL_WINDOW) -= 1;
+            }
+            self.send(UnixMessage::Chunk { id, origin: ChunkOrigin::Request, data: chunk.to_vec() }).await?;
+        }
+        Ok(())
+    }
+
+    /// End the body of request `id`
+    pub async fn end_body(&mut self, id: u64) -> Result<(), UnicomError>{
+        self.credit.remove(&(id, ChunkOrigin::Request));
+        self.send(UnixMessage::Chunk { id, origin: ChunkOrigin::Request, data: Vec::new() }).await
+    }
+
+    /// Wait for the answer to request `id`, streamed chunks are joined
+    pub async fn response(&mut self, id: u64) -> Result<Vec<u8>, UnicomError>{
+        Ok(self.response_with_head(id).await?.data)
+    }
+
+    /// Wait for the answer to request `id` with the status, headers and content type the node set.
+    /// Other frames are kept for `next` and the answers to other requests
+    pub async fn response_with_head(&mut self, id: u64) -> Result<UnicomResponse, UnicomError>{
+        let (mut answer, others): (VecDeque<_>, VecDeque<_>) = std::mem::take(&mut self.buffered).into_iter()
+            .partition(|message| answers(message, id));
+        self.buffered = others;
+        let mut response = UnicomResponse::default();
+        loop{
+            let message = match answer.pop_front(){
+                Some(message) => message,
+                None => self.read().await?,
+            };
+            match message{
+                UnixMessage::Head { id: rid, head } if rid == id => response.head = head,
+                UnixMessage::Response { id: rid, data } if rid == id => return Ok(UnicomResponse { data, head: response.head }),
+                UnixMessage::Error { id: rid, error } if rid == id => return Err(error),
+                UnixMessage::Chunk { id: rid, origin: ChunkOrigin::Response, data } if rid == id => {
+                    if data.is_empty(){
+                        return Ok(response)
+                    }
+                    response.data.extend_from_slice(&data);
+                    if self.flow_control(){
+                        self.send(UnixMessage::Window { id, origin: ChunkOrigin::Response, credit: 1 }).await?;
+                    }
+                },
+                message => self.buffered.push_back(message),
+            }
+        }
+    }
+
+    /// Send a request and wait for its answer
+    pub async fn call(&mut self, api: &str, method: MethodKind, parameters: Map<String, Value>) -> Result<Vec<u8>, UnicomError>{
+        let id = self.request(api, method, parameters).await?;
+        self.response(id).await
+    }
+
+    pub async fn quit(&mut self) -> Result<(), UnicomError>{
+        self.send(UnixMessage::Quit).await
+    }
+}
+
+/// Whether `message` is part of the answer to request `id`
+fn answers(message: &UnixMessage, id: u64) -> bool{
+    match message{
+        UnixMessage::Head { id: rid, .. } | UnixMessage::Response { id: rid, .. } | UnixMessage::Error { id: rid, .. } => *rid == id,
+        UnixMessage::Chunk { id: rid, origin: ChunkOrigin::Response, .. } => *rid == id,
+        _ => false,
+    }
+}
@@ -0,0 +1,87 @@
+use tokio::io::{duplex, AsyncWriteExt, DuplexStream};
+use unicom_lib::arch::unix::{read_message, write_message, ChunkOrigin, UnixMessage, UnixReader, UnixWriter, HEAD_SIZE};
+use unicom_lib::error::UnicomErrorKind;
+use unicom_lib::node::message::{request::UnicomRequest, response::ResponseHead};
+
+/// Raw frame with any kind and body, to send what `UnixWriter` would refuse to encode
+fn frame(kind: u8, id: u64, body: &[u8]) -> Vec<u8>{
+    let mut frame = Vec::with_capacity(HEAD_SIZE + body.len());
+    frame.push(kind);
+    frame.extend_from_slice(&id.to_le_bytes());
+    frame.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    frame.extend_from_slice(body);
+    frame
+}
+
+/// Reader over a pipe of `size` bytes, every read returns at most that much
+fn pipe(size: usize) -> (UnixReader<DuplexStream>, DuplexStream){
+    let (reader, writer) = duplex(size);
+    (UnixReader::new(reader), writer)
+}
+
+#[tokio::test]
+async fn frames_split_across_reads(){
+    let (mut reader, writer) = pipe(3);
+    let large = (0..100_000).map(|i| i as u8).collect::<Vec<u8>>();
+    let sent = large.clone();
+    let writing = tokio::spawn(async move {
+        let mut writer = UnixWriter::new(writer);
+        let mut request = UnicomRequest::new();
+        request.name = "echo".to_string();
+        write_message(&mut writer, UnixMessage::Request { id: 1, data: request }).await.unwrap();
+        write_message(&mut writer, UnixMessage::Head { id: 1, head: ResponseHead::new().with_status(201) }).await.unwrap();
+        write_message(&mut writer, UnixMessage::Response { id: 1, data: sent }).await.unwrap();
+        write_message(&mut writer, UnixMessage::Chunk { id: 2, origin: ChunkOrigin::Request, data: b"chunk".to_vec() }).await.unwrap();
+        write_message(&mut writer, UnixMessage::Ping { id: 3 }).await.unwrap();
+    });
+    assert!(matches!(read_message(&mut reader).await.unwrap(), UnixMessage::Request { id: 1, data } if data.name == "echo"));
+    assert!(matches!(read_message(&mut reader).await.unwrap(), UnixMessage::Head { id: 1, head } if head.status == Some(201)));
+    assert!(matches!(read_message(&mut reader).await.unwrap(), UnixMessage::Response { id: 1, data } if data == large));
+    assert!(matches!(read_message(&mut reader).await.unwrap(), UnixMessage::Chunk { id: 2, origin: ChunkOrigin::Request, data } if data == b"chunk"));
+    assert!(matches!(read_message(&mut reader).await.unwrap(), UnixMessage::Ping { id: 3 }));
+    writing.await.unwrap();
+}
+
+#[tokio::test]
+async fn head_cut_by_the_end_of_stream(){
+    let (mut reader, mut writer) = pipe(64);
+    writer.write_all(&frame(6, 1, &[])[..HEAD_SIZE - 4]).await.unwrap();
+    drop(writer);
+    let error = read_message(&mut reader).await.unwrap_err();
+    assert!(matches!(error.kind(), UnicomErrorKind::DataInvalid));
+}
+
+#[tokio::test]
+async fn undecodable_frames_are_skipped(){
+    let (mut reader, mut writer) = pipe(5);
+    let writing = tokio::spawn(async move {
+        writer.write_all(&frame(1, 1, b"{ not json")).await.unwrap();
+        writer.write_all(&frame(3, 2, &[9, 1, 2, 3])).await.unwrap();
+        writer.write_all(&frame(200, 3, b"unknown body")).await.unwrap();
+        writer.write_all(&frame(6, 4, &[])).await.unwrap();
+    });
+    assert!(matches!(read_message(&mut reader).await.unwrap(), UnixMessage::Rejected { id: 1, origin: ChunkOrigin::Request, .. }));
+    assert!(matches!(read_message(&mut reader).await.unwrap(), UnixMessage::Invalid { id: 2, .. }));
+    assert!(matches!(read_message(&mut reader).await.unwrap(), UnixMessage::Invalid { id: 3, .. }));
+    assert!(matches!(read_message(&mut reader).await.unwrap(), UnixMessage::Ping { id: 4 }));
+    writing.await.unwrap();
+}
+
+#[tokio::test]
+async fn frames_over_the_limit_are_rejected(){
+    let (reader, mut writer) = duplex(64);
+    let mut reader = UnixReader::with_max_frame_size(reader, 16);
+    let writing = tokio::spawn(async move {
+        writer.write_all(&frame(1, 1, &[b' '; 100])).await.unwrap();
+        let mut chunk = vec![2];
+        chunk.extend_from_slice(&[0; 100]);
+        writer.write_all(&frame(3, 2, &chunk)).await.unwrap();
+        writer.write_all(&frame(2, 3, &[0; 16])).await.unwrap();
+    });
+    let rejected = read_message(&mut reader).await.unwrap();
+    assert!(matches!(rejected, UnixMessage::Rejected { id: 1, origin: ChunkOrigin::Request, error } if matches!(error.kind(), UnicomErrorKind::OutOfMemory)));
+    assert!(matches!(read_message(&mut reader).await.unwrap(), UnixMessage::Rejected { id: 2, origin: ChunkOrigin::Response, .. }));
+    // a frame at the limit is still read
+    assert!(matches!(read_message(&mut reader).await.unwrap(), UnixMessage::Response { id: 3, data } if data.len() == 16));
+    writing.await.unwrap();
+}
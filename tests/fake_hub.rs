@@ -0,0 +1,263 @@
+use std::{sync::{Arc, atomic::{AtomicBool, Ordering}}, time::Duration};
+
+use bytes::Bytes;
+use futures::StreamExt;
+use serde_json::Map;
+use tokio::sync::Notify;
+use unicom_lib::{async_trait, ServerConnection, UnicomApi};
+use unicom_lib::arch::unix::{ChunkOrigin, UnixMessage, CHUNK_SIZE, INITIAL_WINDOW};
+use unicom_lib::config::{ConnectionConfig, Manifest};
+use unicom_lib::error::{UnicomError, UnicomErrorKind};
+use unicom_lib::context::RequestContext;
+use unicom_lib::node::{api::{ApiMethod, MethodKind}, message::{request::UnicomRequest, response::ResponseHead, stream::BodyStream}, utils::limit::ApiOptions};
+use unicom_lib::router::Router;
+use unicom_lib::testing::FakeHub;
+
+async fn start(apis: Vec<(Arc<dyn UnicomApi>, ApiOptions)>) -> FakeHub{
+    start_with(ConnectionConfig::default(), apis).await.1
+}
+
+async fn start_with(connection: ConnectionConfig, apis: Vec<(Arc<dyn UnicomApi>, ApiOptions)>) -> (Arc<ServerConnection>, FakeHub){
+    let mut server = ServerConnection::from_manifest("memory://node".parse().unwrap(), Manifest::new("node")).unwrap();
+    server.set_connection_config(connection);
+    for (api, options) in apis{
+        server.add_api_with_options(api, options);
+    }
+    let server = Arc::new(server);
+    let (hub, _notify) = FakeHub::start(&server).await.unwrap();
+    (server, hub)
+}
+
+/// Id of the next request the node sends to the hub
+async fn next_request(hub: &mut FakeHub) -> u64{
+    match hub.next().await.unwrap(){
+        UnixMessage::Request { id, .. } => id,
+        message => panic!("expected a request, got {:?}", message),
+    }
+}
+
+fn echo() -> Arc<dyn UnicomApi>{
+    Arc::new(Router::new("echo").get(vec![], |_server, request, _context| Box::pin(async move {
+        Ok(request.parameters.get("text").and_then(|text| text.as_str()).unwrap_or_default().as_bytes().to_vec())
+    })))
+}
+
+/// Handler notifying `started` then blocked until `release` is notified, `dropped` is set when its future is dropped
+fn blocking(started: Arc<Notify>, release: Arc<Notify>, dropped: Arc<AtomicBool>) -> Arc<dyn UnicomApi>{
+    struct SetOnDrop(Arc<AtomicBool>);
+    impl Drop for SetOnDrop{
+        fn drop(&mut self){
+            self.0.store(true, Ordering::SeqCst);
+        }
+    }
+    Arc::new(Router::new("blocking").get(vec![], move |_server, _request, _context| {
+        let (started, release, dropped) = (started.clone(), release.clone(), dropped.clone());
+        Box::pin(async move {
+            let _guard = SetOnDrop(dropped);
+            started.notify_one();
+            release.notified().await;
+            Ok(b"released".to_vec())
+        })
+    }))
+}
+
+/// Handler answering its request body, read slowly once `release` is notified
+fn upload(release: Arc<Notify>) -> Arc<dyn UnicomApi>{
+    Arc::new(Router::new("upload").post(vec![], move |_server, request, _context| {
+        let release = release.clone();
+        Box::pin(async move {
+            release.notified().await;
+            let mut body = request.take_body().ok_or_else(|| UnicomError::new(UnicomErrorKind::Empty, "no body"))?;
+            let mut data = Vec::new();
+            while let Some(chunk) = body.next().await{
+                data.extend_from_slice(&chunk?);
+                tokio::time::sleep(Duration::from_millis(2)).await;
+            }
+            Ok(data)
+        })
+    }))
+}
+
+/// Api streaming the same body as its answer to every request
+struct Download(Vec<u8>);
+
+#[async_trait]
+impl UnicomApi for Download{
+    fn name(&self) -> String{
+        "download".to_string()
+    }
+
+    fn description(&self) -> Vec<ApiMethod>{
+        vec![ApiMethod::new(MethodKind::GET, vec![])]
+    }
+
+    async fn api_get(&self, _server: &Arc<ServerConnection>, _request: &UnicomRequest, _context: &RequestContext) -> Result<Vec<u8>, UnicomError>{
+        Ok(Vec::new())
+    }
+
+    async fn api_put(&self, _server: &Arc<ServerConnection>, _request: &UnicomRequest, _context: &RequestContext) -> Result<Vec<u8>, UnicomError>{
+        Err(UnicomError::new(UnicomErrorKind::MethodNotAllowed, "download is read only"))
+    }
+
+    async fn api_post(&self, _server: &Arc<ServerConnection>, _request: &UnicomRequest, _context: &RequestContext) -> Result<Vec<u8>, UnicomError>{
+        Err(UnicomError::new(UnicomErrorKind::MethodNotAllowed, "download is read only"))
+    }
+
+    async fn api_delete(&self, _server: &Arc<ServerConnection>, _request: &UnicomRequest, _context: &RequestContext) -> Result<Vec<u8>, UnicomError>{
+        Err(UnicomError::new(UnicomErrorKind::MethodNotAllowed, "download is read only"))
+    }
+
+    async fn api_stream(&self, _server: &Arc<ServerConnection>, _request: &UnicomRequest, _context: &RequestContext) -> Result<Option<BodyStream>, UnicomError>{
+        let chunks: Vec<_> = self.0.chunks(CHUNK_SIZE).map(|chunk| Ok(Bytes::copy_from_slice(chunk))).collect();
+        Ok(Some(Box::pin(futures::stream::iter(chunks))))
+    }
+}
+
+/// Body of `chunks` full chunk frames
+fn large_body(chunks: usize) -> Vec<u8>{
+    (0..chunks * CHUNK_SIZE).map(|i| (i % 251) as u8).collect()
+}
+
+fn parameters(text: &str) -> Map<String, serde_json::Value>{
+    serde_json::json!({ "text": text }).as_object().unwrap().clone()
+}
+
+#[tokio::test]
+async fn request_response(){
+    let mut hub = start(vec![(echo(), ApiOptions::default())]).await;
+    let data = hub.call("echo", MethodKind::GET, parameters("hello")).await.unwrap();
+    assert_eq!(data, b"hello");
+}
+
+#[tokio::test]
+async fn answers_out_of_order_are_kept(){
+    let mut hub = start(vec![(echo(), ApiOptions::default())]).await;
+    let first = hub.request("echo", MethodKind::GET, parameters("first")).await.unwrap();
+    let second = hub.request("echo", MethodKind::GET, parameters("second")).await.unwrap();
+    assert_eq!(hub.response(second).await.unwrap(), b"second");
+    assert_eq!(hub.response(first).await.unwrap(), b"first");
+}
+
+#[tokio::test]
+async fn handler_error(){
+    let api = Router::new("missing").get(vec![], |_server, _request, _context| Box::pin(async move {
+        Err(UnicomError::new(UnicomErrorKind::NotFound, "nothing here"))
+    }));
+    let mut hub = start(vec![(Arc::new(api), ApiOptions::default())]).await;
+    let error = hub.call("missing", MethodKind::GET, Map::new()).await.unwrap_err();
+    assert!(matches!(error.kind(), UnicomErrorKind::NotFound));
+    assert_eq!(error.description, "nothing here");
+}
+
+#[tokio::test]
+async fn method_not_allowed(){
+    let mut hub = start(vec![(echo(), ApiOptions::default())]).await;
+    let error = hub.call("echo", MethodKind::DELETE, Map::new()).await.unwrap_err();
+    assert!(matches!(error.kind(), UnicomErrorKind::MethodNotAllowed));
+}
+
+#[tokio::test]
+async fn handler_panic(){
+    let api = Router::new("panics").get(vec![], |_server, _request, _context| Box::pin(async move {
+        panic!("handler failed")
+    }));
+    let mut hub = start(vec![(Arc::new(api), ApiOptions::default()), (echo(), ApiOptions::default())]).await;
+    let error = hub.call("panics", MethodKind::GET, Map::new()).await.unwrap_err();
+    assert!(matches!(error.kind(), UnicomErrorKind::Internal));
+    assert!(error.description.contains("handler failed"));
+    // the node keeps serving after a panic
+    assert_eq!(hub.call("echo", MethodKind::GET, parameters("still up")).await.unwrap(), b"still up");
+}
+
+#[tokio::test]
+async fn busy_when_queue_is_full(){
+    let (started, release) = (Arc::new(Notify::new()), Arc::new(Notify::new()));
+    let api = blocking(started.clone(), release.clone(), Arc::new(AtomicBool::new(false)));
+    let mut hub = start(vec![(api, ApiOptions::new(1, 0))]).await;
+    let running = hub.request("blocking", MethodKind::GET, Map::new()).await.unwrap();
+    started.notified().await;
+    let error = hub.call("blocking", MethodKind::GET, Map::new()).await.unwrap_err();
+    assert!(matches!(error.kind(), UnicomErrorKind::Busy));
+    release.notify_one();
+    assert_eq!(hub.response(running).await.unwrap(), b"released");
+}
+
+#[tokio::test]
+async fn cancel_stops_the_handler(){
+    let (started, dropped) = (Arc::new(Notify::new()), Arc::new(AtomicBool::new(false)));
+    let api = blocking(started.clone(), Arc::new(Notify::new()), dropped.clone());
+    let mut hub = start(vec![(api, ApiOptions::default()), (echo(), ApiOptions::default())]).await;
+    let id = hub.request("blocking", MethodKind::GET, Map::new()).await.unwrap();
+    started.notified().await;
+    hub.send(UnixMessage::Cancel { id }).await.unwrap();
+    tokio::time::timeout(Duration::from_secs(1), async {
+        while !dropped.load(Ordering::SeqCst){
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+    }).await.expect("handler still running after cancel");
+    assert_eq!(hub.call("echo", MethodKind::GET, parameters("after")).await.unwrap(), b"after");
+}
+
+#[tokio::test]
+async fn zero_concurrency_is_refused(){
+    let built = ServerConnection::builder().address("memory://node".parse().unwrap()).manifest(Manifest::new("node"))
+        .api_with_options(echo(), ApiOptions::new(0, 4)).build();
+    assert!(matches!(built.err().unwrap().kind(), UnicomErrorKind::ParameterInvalid));
+}
+
+#[tokio::test]
+async fn request_over_the_frame_limit(){
+    let connection = ConnectionConfig { max_frame_size: 1024, ..ConnectionConfig::default() };
+    let (_server, mut hub) = start_with(connection, vec![(echo(), ApiOptions::default())]).await;
+    let error = hub.call("echo", MethodKind::GET, parameters(&"x".repeat(2048))).await.unwrap_err();
+    assert!(matches!(error.kind(), UnicomErrorKind::OutOfMemory));
+    assert_eq!(hub.call("echo", MethodKind::GET, parameters("small")).await.unwrap(), b"small");
+}
+
+#[tokio::test]
+async fn request_stream_returns_the_head(){
+    let (server, mut hub) = start_with(ConnectionConfig::default(), vec![]).await;
+    let caller = tokio::spawn(async move { server.request_stream("other", "file", Map::new()).await });
+    let id = next_request(&mut hub).await;
+    hub.send(UnixMessage::Head { id, head: ResponseHead::new().with_status(206).with_content_type("text/plain") }).await.unwrap();
+    hub.send(UnixMessage::Chunk { id, origin: ChunkOrigin::Response, data: b"part".to_vec() }).await.unwrap();
+    hub.send(UnixMessage::Chunk { id, origin: ChunkOrigin::Response, data: Vec::new() }).await.unwrap();
+    let (head, stream) = caller.await.unwrap().unwrap();
+    assert_eq!(head.status, Some(206));
+    assert_eq!(head.content_type.as_deref(), Some("text/plain"));
+    assert_eq!(stream.to_vec().await.unwrap(), b"part");
+}
+
+#[tokio::test]
+async fn request_stream_answered_with_an_error(){
+    let (server, mut hub) = start_with(ConnectionConfig::default(), vec![]).await;
+    let caller = tokio::spawn(async move { server.request_stream("other", "file", Map::new()).await });
+    let id = next_request(&mut hub).await;
+    hub.send(UnixMessage::Error { id, error: UnicomError::new(UnicomErrorKind::NotFound, "no file") }).await.unwrap();
+    let error = caller.await.unwrap().unwrap_err();
+    assert!(matches!(error.kind(), UnicomErrorKind::NotFound));
+}
+
+#[tokio::test]
+async fn slow_body_reader_gets_the_whole_body(){
+    let release = Arc::new(Notify::new());
+    let mut hub = start(vec![(upload(release.clone()), ApiOptions::default()), (echo(), ApiOptions::default())]).await;
+    let body = large_body(3 * INITIAL_WINDOW);
+    let id = hub.request_with_body("upload", MethodKind::POST, Map::new()).await.unwrap();
+    // a whole window waits for a handler that reads nothing yet, the node still answers other requests
+    hub.send_body(id, &body[..INITIAL_WINDOW * CHUNK_SIZE]).await.unwrap();
+    assert_eq!(hub.call("echo", MethodKind::GET, parameters("not delayed")).await.unwrap(), b"not delayed");
+    release.notify_one();
+    hub.send_body(id, &body[INITIAL_WINDOW * CHUNK_SIZE..]).await.unwrap();
+    hub.end_body(id).await.unwrap();
+    assert!(hub.response(id).await.unwrap() == body);
+}
+
+#[tokio::test]
+async fn streamed_response_waits_for_credit(){
+    let body = large_body(3 * INITIAL_WINDOW);
+    let mut hub = start(vec![(Arc::new(Download(body.clone())), ApiOptions::default()), (echo(), ApiOptions::default())]).await;
+    let id = hub.request("download", MethodKind::GET, Map::new()).await.unwrap();
+    assert_eq!(hub.call("echo", MethodKind::GET, parameters("not delayed")).await.unwrap(), b"not delayed");
+    assert!(hub.response(id).await.unwrap() == body);
+}
@@ -0,0 +1,96 @@
+use std::{fs::File, io::{Read, Write}, os::fd::OwnedFd, time::Duration};
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::UnixStream;
+use unicom_lib::arch::unix::{read_message, ChunkOrigin, FdReader, FdWriter, UnixMessage, UnixReader, UnixWriter, MAX_FILES};
+use unicom_lib::arch::writer::WriterTask;
+
+fn pair() -> (FdReader, FdWriter){
+    let (reader, writer) = UnixStream::pair().unwrap();
+    let (reader, _) = reader.into_split();
+    let (_, writer) = writer.into_split();
+    (FdReader::new(reader), FdWriter::new(writer))
+}
+
+/// Socket end to pass around and its peer, which reads end of file once every copy of the end is closed
+fn probe() -> (OwnedFd, std::os::unix::net::UnixStream){
+    let (end, peer) = std::os::unix::net::UnixStream::pair().unwrap();
+    peer.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+    (end.into(), peer)
+}
+
+fn null() -> OwnedFd{
+    File::open("/dev/null").unwrap().into()
+}
+
+/// Write one byte carrying `fds`
+async fn send_fds(writer: &mut FdWriter, fds: Vec<OwnedFd>){
+    writer.outgoing().lock().unwrap().extend(fds);
+    writer.write_all(b"x").await.unwrap();
+}
+
+#[tokio::test]
+async fn truncated_rights_are_closed(){
+    let (mut reader, mut writer) = pair();
+    let (end, mut peer) = probe();
+    let mut fds = vec![end];
+    fds.extend((0..MAX_FILES + 8).map(|_| null()));
+    send_fds(&mut writer, fds).await;
+    let mut byte = [0; 1];
+    reader.read_exact(&mut byte).await.unwrap();
+    assert!(reader.received().lock().unwrap().is_empty());
+    // the probe end received before the cut was closed with the others
+    assert_eq!(peer.read(&mut byte).expect("probe end still open"), 0);
+}
+
+#[tokio::test]
+async fn waiting_descriptors_are_capped(){
+    let (mut reader, mut writer) = pair();
+    let writes = 5;
+    for _ in 0..writes{
+        send_fds(&mut writer, (0..MAX_FILES).map(|_| null()).collect()).await;
+    }
+    let mut bytes = vec![0; writes];
+    reader.read_exact(&mut bytes).await.unwrap();
+    assert_eq!(reader.received().lock().unwrap().len(), 4 * MAX_FILES);
+}
+
+#[tokio::test]
+async fn concurrent_files_frames_arrive_intact(){
+    let (reader, writer) = pair();
+    let received = reader.received();
+    let outgoing = writer.outgoing();
+    let task = WriterTask::spawn(UnixWriter::new(writer), Some(outgoing), 4, true);
+    let frames = 20u64;
+    let mut senders = Vec::new();
+    for id in 1..=frames{
+        let sender = task.sender();
+        senders.push(tokio::spawn(async move {
+            // every descriptor of frame `id` reads `id` on the other side
+            let files: Vec<OwnedFd> = (0..id % 3 + 1).map(|_| {
+                let (end, mut peer) = std::os::unix::net::UnixStream::pair().unwrap();
+                peer.write_all(&id.to_le_bytes()).unwrap();
+                end.into()
+            }).collect();
+            sender.send(UnixMessage::Response { id, data: vec![0; 1000] }, Vec::new()).await.unwrap();
+            sender.send(UnixMessage::Files { id, origin: ChunkOrigin::Response, count: files.len() }, files).await.unwrap();
+        }));
+    }
+    let mut reader = UnixReader::new(reader);
+    let mut files_frames = 0;
+    while files_frames < frames{
+        if let UnixMessage::Files { id, count, .. } = read_message(&mut reader).await.unwrap(){
+            let fds: Vec<OwnedFd> = received.lock().unwrap().drain(..count).collect();
+            for fd in fds{
+                let mut content = [0; 8];
+                std::os::unix::net::UnixStream::from(fd).read_exact(&mut content).unwrap();
+                assert_eq!(u64::from_le_bytes(content), id);
+            }
+            files_frames += 1;
+        }
+    }
+    assert!(received.lock().unwrap().is_empty());
+    for sender in senders{
+        sender.await.unwrap();
+    }
+}
@@ -0,0 +1,65 @@
+use std::{sync::Arc, time::Duration};
+
+use tokio::io::{duplex, split};
+use unicom_lib::ServerConnection;
+use unicom_lib::arch::Connection;
+use unicom_lib::arch::unix::{read_handshake, read_registration, write_init, Capabilities, Handshake, UnixReader, UnixWriter, PROTOCOL_VERSION};
+use unicom_lib::config::{ConnectionConfig, Manifest, PeerPolicy};
+use unicom_lib::error::UnicomErrorKind;
+use unicom_lib::node::NodeConfig;
+
+fn capabilities(list: &[Capabilities]) -> Capabilities{
+    let mut capabilities = Capabilities::empty();
+    for capability in list{
+        capabilities.insert(*capability);
+    }
+    capabilities
+}
+
+fn node() -> NodeConfig{
+    Manifest::new("node").try_into().unwrap()
+}
+
+/// Node offering `node_offer` and hub offering `hub_offer` over a pipe, returns what each side ends up with
+async fn negotiate(node_offer: Handshake, hub_offer: Handshake) -> (Result<Handshake, UnicomErrorKind>, Result<Handshake, UnicomErrorKind>){
+    let (node_side, hub_side) = duplex(4096);
+    let (node_reader, node_writer) = split(node_side);
+    let (hub_reader, hub_writer) = split(hub_side);
+    let hub = tokio::spawn(async move {
+        let (mut reader, mut writer) = (UnixReader::new(hub_reader), UnixWriter::new(hub_writer));
+        read_registration(&mut reader, &mut writer, &hub_offer, None, &PeerPolicy::default()).await
+            .map(|(_, handshake, _)| handshake).map_err(|e| e.kind().clone())
+    });
+    let (mut reader, mut writer) = (UnixReader::new(node_reader), UnixWriter::new(node_writer));
+    write_init(&mut writer, &node(), &node_offer).await.unwrap();
+    let node = read_handshake(&mut reader, &node_offer).await.map_err(|e| e.kind().clone());
+    (node, hub.await.unwrap())
+}
+
+#[tokio::test]
+async fn shared_capabilities_are_kept(){
+    let node_offer = Handshake::new(capabilities(&[Capabilities::STREAMING, Capabilities::HEARTBEAT]));
+    let hub_offer = Handshake::new(capabilities(&[Capabilities::STREAMING, Capabilities::CANCELLATION]));
+    let (node, hub) = negotiate(node_offer, hub_offer).await;
+    let expected = Handshake { version: PROTOCOL_VERSION, capabilities: Capabilities::STREAMING };
+    assert_eq!(node.unwrap(), expected);
+    assert_eq!(hub.unwrap(), expected);
+}
+
+#[tokio::test]
+async fn old_version_is_told_why(){
+    let node_offer = Handshake { version: 0, capabilities: Capabilities::supported() };
+    let (node, hub) = negotiate(node_offer, Handshake::default()).await;
+    assert!(matches!(hub.unwrap_err(), UnicomErrorKind::VersionMismatch));
+    assert!(matches!(node.unwrap_err(), UnicomErrorKind::VersionMismatch));
+}
+
+#[tokio::test]
+async fn silent_hub_times_out(){
+    let mut server = ServerConnection::from_manifest("memory://node".parse().unwrap(), Manifest::new("node")).unwrap();
+    server.set_connection_config(ConnectionConfig { handshake_timeout: 50, ..ConnectionConfig::default() });
+    let (node_side, _hub_side) = duplex(4096);
+    let (reader, writer) = split(node_side);
+    let notify = ServerConnection::run_on(&Arc::new(server), Connection::new(reader, writer)).await;
+    tokio::time::timeout(Duration::from_secs(1), notify.notified()).await.expect("run still waiting for the handshake");
+}
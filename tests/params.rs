@@ -0,0 +1,43 @@
+use serde_json::{json, Map, Value};
+use unicom_lib::UnicomParams;
+use unicom_lib::error::UnicomErrorKind;
+use unicom_lib::node::api::{check_parameters, Parameter, ValueKind};
+
+#[derive(UnicomParams)]
+struct Search{
+    query: String,
+    limit: Option<i64>,
+    ratio: f64,
+}
+
+fn parameters(value: Value) -> Map<String, Value>{
+    value.as_object().unwrap().clone()
+}
+
+#[test]
+fn null_optional_parameter_is_missing(){
+    let search = Search::from_parameters(&parameters(json!({ "query": "rust", "limit": null, "ratio": 0.5 }))).unwrap();
+    assert_eq!(search.query, "rust");
+    assert_eq!(search.limit, None);
+}
+
+#[test]
+fn null_mandatory_parameter_is_refused(){
+    let error = Search::from_parameters(&parameters(json!({ "query": null, "ratio": 0.5 }))).err().unwrap();
+    assert!(matches!(error.kind(), UnicomErrorKind::ParameterInvalid));
+}
+
+#[test]
+fn integer_given_for_a_float(){
+    let search = Search::from_parameters(&parameters(json!({ "query": "rust", "ratio": 2 }))).unwrap();
+    assert_eq!(search.ratio, 2.0);
+    let expected = [Parameter::new("ratio", ValueKind::Float, true)];
+    assert!(check_parameters(&expected, &parameters(json!({ "ratio": 2 }))).is_ok());
+    assert!(check_parameters(&expected, &parameters(json!({ "ratio": "2" }))).is_err());
+}
+
+#[test]
+fn float_given_for_an_integer_is_refused(){
+    let error = Search::from_parameters(&parameters(json!({ "query": "rust", "limit": 2.5, "ratio": 1.0 }))).err().unwrap();
+    assert!(matches!(error.kind(), UnicomErrorKind::ParameterInvalid));
+}
@@ -0,0 +1,273 @@
+//! Macros of `unicom-lib`, use them through its re-exports `unicom_lib::UnicomParams` and `unicom_lib::unicom_api`
+
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::quote;
+use syn::{parse_macro_input, Data, DeriveInput, Error, Fields, FnArg, GenericArgument, Ident, ImplItem, ItemImpl, LitStr, PathArguments, Type};
+
+/// Kinds accepted by `#[unicom(kind = "...")]`, the prefixes read by `ValueKind::from`
+const KINDS: [&str; 8] = ["int", "str", "flt", "ipt", "sid", "usr", "ses", "url"];
+
+/// Methods of `MethodKind` with the attribute marking their handler in `#[unicom_api]`, the last ones
+/// have a default in `UnicomApi` used when no handler is marked
+const METHODS: [(&str, &str, &str); 7] = [
+    ("get", "GET", "api_get"),
+    ("put", "PUT", "api_put"),
+    ("post", "POST", "api_post"),
+    ("delete", "DELETE", "api_delete"),
+    ("patch", "PATCH", "api_patch"),
+    ("head", "HEAD", "api_head"),
+    ("options", "OPTIONS", "api_options"),
+];
+const REQUIRED_METHODS: usize = 4;
+
+/// Implement `UnicomParams` for a struct with named fields, see `unicom_lib::node::api::UnicomParams`
+#[proc_macro_derive(UnicomParams, attributes(unicom))]
+pub fn derive_unicom_params(input: TokenStream) -> TokenStream{
+    let input = parse_macro_input!(input as DeriveInput);
+    params(input).unwrap_or_else(Error::into_compile_error).into()
+}
+
+/// Implement `UnicomApi` from an impl block whose handlers are marked `#[get]`, `#[put]`, `#[post]`, `#[delete]`,
+/// `#[patch]`, `#[head]` or `#[options]`.
+///
+/// A handler is an async method taking `&self` and, in any order, `&Arc<ServerConnection>`, `&UnicomRequest`,
+/// `&RequestContext` and one struct implementing `UnicomParams` decoded from the request parameters.
+/// The api is named by `#[unicom_api(name = "...")]`, the type name in lower case by default.
+#[proc_macro_attribute]
+pub fn unicom_api(args: TokenStream, input: TokenStream) -> TokenStream{
+    let mut name = None;
+    let parser = syn::meta::parser(|meta| {
+        if meta.path.is_ident("name"){
+            name = Some(meta.value()?.parse::<LitStr>()?.value());
+            Ok(())
+        }
+        else{
+            Err(meta.error("unsupported unicom_api argument, expected `name`"))
+        }
+    });
+    parse_macro_input!(args with parser);
+    let item = parse_macro_input!(input as ItemImpl);
+    api(name, item).unwrap_or_else(Error::into_compile_error).into()
+}
+
+fn params(input: DeriveInput) -> Result<TokenStream2, Error>{
+    let ident = &input.ident;
+    let fields = match &input.data{
+        Data::Struct(data) => match &data.fields{
+            Fields::Named(fields) => &fields.named,
+            _ => return Err(Error::new_spanned(&input, "UnicomParams needs a struct with named fields")),
+        },
+        _ => return Err(Error::new_spanned(&input, "UnicomParams needs a struct with named fields")),
+    };
+
+    let mut parameters = Vec::new();
+    let mut decoders = Vec::new();
+    for field in fields{
+        let field_ident = field.ident.as_ref().expect("named field");
+        let mut name = field_ident.to_string();
+        let mut kind = None;
+        for attr in field.attrs.iter().filter(|attr| attr.path().is_ident("unicom")){
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("rename"){
+                    name = meta.value()?.parse::<LitStr>()?.value();
+                    Ok(())
+                }
+                else if meta.path.is_ident("kind"){
+                    let value = meta.value()?.parse::<LitStr>()?;
+                    if !KINDS.iter().any(|prefix| value.value().starts_with(prefix)){
+                        return Err(Error::new_spanned(&value, format!("unknown parameter kind, expected one of {}", KINDS.join(", "))))
+                    }
+                    kind = Some(value);
+                    Ok(())
+                }
+                else{
+                    Err(meta.error("unsupported unicom attribute, expected `rename` or `kind`"))
+                }
+            })?;
+        }
+
+        let (inner, mandatory) = match option_inner(&field.ty){
+            Some(inner) => (inner, false),
+            None => (&field.ty, true),
+        };
+        let kind = match kind{
+            Some(kind) => quote!(::unicom_lib::node::api::ValueKind::from(#kind)),
+            None => quote!(<#inner as ::unicom_lib::node::api::ParameterKind>::kind()),
+        };
+        parameters.push(quote!(::unicom_lib::node::api::Parameter::new(#name, #kind, #mandatory)));
+        decoders.push(quote!(#field_ident: ::unicom_lib::node::api::decode_parameter(parameters, #name)?));
+    }
+
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote!{
+        impl #impl_generics ::unicom_lib::node::api::UnicomParams for #ident #ty_generics #where_clause{
+            fn parameters() -> ::std::vec::Vec<::unicom_lib::node::api::Parameter>{
+                ::std::vec![#(#parameters),*]
+            }
+
+            fn from_parameters(parameters: &::unicom_lib::serde_json::Map<::std::string::String, ::unicom_lib::serde_json::Value>) -> ::std::result::Result<Self, ::unicom_lib::error::UnicomError>{
+                ::unicom_lib::node::api::check_parameters(&<Self as ::unicom_lib::node::api::UnicomParams>::parameters(), parameters)?;
+                ::std::result::Result::Ok(#ident{
+                    #(#decoders),*
+                })
+            }
+        }
+    })
+}
+
+/// `T` of a field typed `Option<T>`
+fn option_inner(ty: &Type) -> Option<&Type>{
+    let Type::Path(path) = ty else { return None };
+    let segment = path.path.segments.last()?;
+    if segment.ident != "Option"{
+        return None
+    }
+    match &segment.arguments{
+        PathArguments::AngleBracketed(arguments) if arguments.args.len() == 1 => match &arguments.args[0]{
+            GenericArgument::Type(inner) => Some(inner),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Handler of one method and the arguments it takes
+struct Route{
+    method: &'static str,
+    trait_method: &'static str,
+    handler: Ident,
+    params: Option<Type>,
+    arguments: Vec<TokenStream2>,
+}
+
+fn api(name: Option<String>, mut item: ItemImpl) -> Result<TokenStream2, Error>{
+    let self_ty = item.self_ty.clone();
+    let name = match name{
+        Some(name) => name,
+        None => match &*self_ty{
+            Type::Path(path) => path.path.segments.last().map(|segment| segment.ident.to_string().to_lowercase()).unwrap_or_default(),
+            _ => return Err(Error::new_spanned(&self_ty, "unicom_api needs a named type or a `name` argument")),
+        },
+    };
+
+    let mut routes: Vec<Route> = Vec::new();
+    for impl_item in &mut item.items{
+        let ImplItem::Fn(function) = impl_item else { continue };
+        let mut marked = None;
+        let mut error = None;
+        function.attrs.retain(|attr| {
+            match METHODS.iter().find(|(attribute, _, _)| attr.path().is_ident(attribute)){
+                Some(method) => {
+                    if marked.is_some(){
+                        error = Some(Error::new_spanned(attr, "a handler answers one method"));
+                    }
+                    marked = Some(*method);
+                    false
+                },
+                None => true,
+            }
+        });
+        if let Some(error) = error{
+            return Err(error)
+        }
+        let Some((_, method, trait_method)) = marked else { continue };
+        if routes.iter().any(|route| route.method == method){
+            return Err(Error::new_spanned(&function.sig, format!("method {} already has a handler", method)))
+        }
+        if function.sig.asyncness.is_none(){
+            return Err(Error::new_spanned(&function.sig, "handlers of unicom_api must be async"))
+        }
+
+        let mut params = None;
+        let mut arguments = Vec::new();
+        for input in &function.sig.inputs{
+            let FnArg::Typed(argument) = input else { continue };
+            match &*argument.ty{
+                Type::Reference(reference) => {
+                    let ident = match &*reference.elem{
+                        Type::Path(path) => path.path.segments.last().map(|segment| segment.ident.to_string()),
+                        _ => None,
+                    };
+                    match ident.as_deref(){
+                        Some("Arc") => arguments.push(quote!(server)),
+                        Some("UnicomRequest") => arguments.push(quote!(request)),
+                        Some("RequestContext") => arguments.push(quote!(context)),
+                        _ => return Err(Error::new_spanned(&argument.ty, "expected &Arc<ServerConnection>, &UnicomRequest, &RequestContext or a parameters struct")),
+                    }
+                },
+                ty => {
+                    if params.is_some(){
+                        return Err(Error::new_spanned(ty, "a handler takes one parameters struct"))
+                    }
+                    params = Some(ty.clone());
+                    arguments.push(quote!(parameters));
+                },
+            }
+        }
+        routes.push(Route { method, trait_method, handler: function.sig.ident.clone(), params, arguments });
+    }
+    if routes.is_empty(){
+        return Err(Error::new_spanned(&self_ty, "unicom_api needs at least one handler marked with a method like #[get]"))
+    }
+
+    let description = routes.iter().map(|route| {
+        let method = Ident::new(route.method, Span::call_site());
+        let parameters = match &route.params{
+            Some(params) => quote!(<#params as ::unicom_lib::node::api::UnicomParams>::parameters()),
+            None => quote!(::std::vec::Vec::new()),
+        };
+        quote!(::unicom_lib::node::api::ApiMethod::new(::unicom_lib::node::api::MethodKind::#method, #parameters))
+    });
+
+    let methods = METHODS.iter().enumerate().filter_map(|(index, (_, method, trait_method))| {
+        let route = routes.iter().find(|route| route.trait_method == *trait_method);
+        if route.is_none() && index >= REQUIRED_METHODS{
+            return None
+        }
+        let trait_method = Ident::new(trait_method, Span::call_site());
+        let body = match route{
+            Some(route) => {
+                let handler = &route.handler;
+                let arguments = &route.arguments;
+                let decode = route.params.as_ref().map(|params| quote!{
+                    let parameters = <#params as ::unicom_lib::node::api::UnicomParams>::from_parameters(&request.parameters)?;
+                });
+                quote!{
+                    #decode
+                    self.#handler(#(#arguments),*).await
+                }
+            },
+            None => quote!{
+                ::std::result::Result::Err(::unicom_lib::error::UnicomError::new(
+                    ::unicom_lib::error::UnicomErrorKind::MethodNotAllowed,
+                    &::std::format!("method {} not allowed on {}", #method, #name),
+                ))
+            },
+        };
+        Some(quote!{
+            #[allow(unused_variables)]
+            async fn #trait_method(&self, server: &::std::sync::Arc<::unicom_lib::ServerConnection>, request: &::unicom_lib::node::message::request::UnicomRequest, context: &::unicom_lib::context::RequestContext) -> ::std::result::Result<::std::vec::Vec<u8>, ::unicom_lib::error::UnicomError>{
+                #body
+            }
+        })
+    });
+
+    let (impl_generics, _, where_clause) = item.generics.split_for_impl();
+    Ok(quote!{
+        #item
+
+        #[::unicom_lib::async_trait]
+        impl #impl_generics ::unicom_lib::UnicomApi for #self_ty #where_clause{
+            fn name(&self) -> ::std::string::String{
+                ::std::string::String::from(#name)
+            }
+
+            fn description(&self) -> ::std::vec::Vec<::unicom_lib::node::api::ApiMethod>{
+                ::std::vec![#(#description),*]
+            }
+
+            #(#methods)*
+        }
+    })
+}